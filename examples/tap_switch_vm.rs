@@ -1,38 +1,53 @@
 extern crate pg;
 
-use pg::{init, Graph, Brick, Vhost, Tap, Switch, Side};
+use pg::{init, Graph, Brick, Vhost, VhostFlags, Tap, Switch, Side, Runner,
+         qemu_memory_args};
+use std::path::Path;
+use std::time::Duration;
 
 fn main() {
     // We create 3 vhost interfaces connected together on a switch
     // A tap interface is also added to the switch
-    // You can then lanch a virtual machine
-    //     -chardev socket,id=char0,path=$SOCKET_PATH
-    //     -netdev type=vhost-user,id=mynet,chardev=char0,vhostforce
-    //     -device virtio-net-pci,csum=off,gso=off,mac=$MAC,netdev=mynet
-    //     -object memory-backend-file,id=mem,size=124M,mem-path=/mnt/huge,share=on
+    // You can then lanch a virtual machine on each vhost with the printed
+    // QEMU arguments
 
     // Initialize dpdk & stuff
     init();
 
     // Create some bricks
-    let mut tap = Brick::Tap(Tap::new("tap"));
-    let mut vh1 = Brick::Vhost(Vhost::new("vhost1", 0).expect("vhost1 creation "));
-    let mut vh2 = Brick::Vhost(Vhost::new("vhost2", 0).expect("vhost2 creation "));
-    let mut vh3 = Brick::Vhost(Vhost::new("vhost3", 0).expect("vhost3 creation "));
-    let mut sw = Brick::Switch(Switch::new("switch", 1, 3, Side::West));
+    let tap = Brick::Tap(Tap::new("tap"));
+    let mut vh1 = Brick::Vhost(Vhost::new("vhost1", VhostFlags::empty())
+        .expect("vhost1 creation "));
+    let mut vh2 = Brick::Vhost(Vhost::new("vhost2", VhostFlags::empty())
+        .expect("vhost2 creation "));
+    let mut vh3 = Brick::Vhost(Vhost::new("vhost3", VhostFlags::empty())
+        .expect("vhost3 creation "));
+    let sw = Brick::Switch(Switch::new("switch", 1, 3, Side::West));
 
-    // Link bricks togather
-    tap.link(&mut sw).unwrap();
-    sw.link(&mut vh1).unwrap();
-    sw.link(&mut vh2).unwrap();
-    sw.link(&mut vh3).unwrap();
+    // Print how to plug a VM on each vhost
+    let mem = qemu_memory_args(124, Path::new("/mnt/huge")).unwrap().join(" ");
+    for (i, vh) in [&mut vh1, &mut vh2, &mut vh3].iter_mut().enumerate() {
+        let mac = [0x52, 0x54, 0x00, 0x00, 0x00, i as u8 + 1];
+        let args = vh.vhost().unwrap().qemu_args(mac).unwrap();
+        println!("{}: qemu-system-x86_64 {} {}", vh.name(), mem, args.join(" "));
+    }
 
     // Put every body in a graph
     let mut g = Graph::new("my network");
-    g.add(tap).add(sw).add(vh1).add(vh2).add(vh3);
+    for brick in [tap, sw, vh1, vh2, vh3] {
+        g.add(brick).unwrap();
+    }
+
+    // Link bricks togather
+    g.link("tap", "switch").unwrap();
+    g.link("switch", "vhost1").unwrap();
+    g.link("switch", "vhost2").unwrap();
+    g.link("switch", "vhost3").unwrap();
 
     println!("Now polling packets from tap and vhost interfaces...");
-    loop {
-        g.poll();
-    }
+    Runner::new(g)
+        .on_error(Duration::from_secs(1), |brick, e, dropped| {
+            eprintln!("{}: {} ({} more errors)", brick, e, dropped);
+        })
+        .run();
 }
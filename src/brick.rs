@@ -17,7 +17,7 @@
 
 use error::Error;
 use packetgraph_sys::{pg_brick, pg_brick_link, pg_brick_unlink_edge, pg_brick_unlink,
-                      pg_brick_poll, pg_brick_dot};
+                      pg_brick_poll, pg_brick_dot, pg_brick_pkts_count_get, pg_brick_refcount};
 use nop::Nop;
 use firewall::Firewall;
 use tap::Tap;
@@ -25,15 +25,19 @@ use switch::Switch;
 use nic::Nic;
 use hub::Hub;
 use vhost::Vhost;
+use queue::Queue;
 use std::process::{Command, Stdio};
 use std::error::Error as StdErr;
 use std::io::prelude::*;
+use std::io::ErrorKind;
 use std::ffi::CStr;
+use std::thread;
+use std::time::{Duration, Instant};
 use libc;
+use Side;
 
-// Maybe use a better wrapper of pg_brick raw pointer
-unsafe impl Send for Brick {}
-unsafe impl Sync for Brick {}
+// Max time given to graphviz to render a graph
+const DOT_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub enum Brick {
     Nop(Nop),
@@ -43,6 +47,7 @@ pub enum Brick {
     Nic(Nic),
     Hub(Hub),
     Vhost(Vhost),
+    Queue(Queue),
 }
 
 impl<'a> Brick {
@@ -111,6 +116,7 @@ impl<'a> Brick {
             Brick::Nic(ref b) => b.pollable(),
             Brick::Hub(ref b) => b.pollable(),
             Brick::Vhost(ref b) => b.pollable(),
+            Brick::Queue(ref b) => b.pollable(),
         }
     }
 
@@ -123,6 +129,7 @@ impl<'a> Brick {
             Brick::Nic(ref b) => b.name.clone(),
             Brick::Hub(ref b) => b.name.clone(),
             Brick::Vhost(ref b) => b.name.clone(),
+            Brick::Queue(ref b) => b.name.clone(),
         }
     }
 
@@ -135,10 +142,11 @@ impl<'a> Brick {
             Brick::Nic(_) => "nic",
             Brick::Hub(_) => "hub",
             Brick::Vhost(_) => "vhost",
+            Brick::Queue(_) => "queue",
         }
     }
 
-    fn get_brick(&mut self) -> *mut pg_brick {
+    fn get_brick(&self) -> *mut pg_brick {
         match *self {
             Brick::Firewall(ref b) => b.brick,
             Brick::Nop(ref b) => b.brick,
@@ -147,6 +155,7 @@ impl<'a> Brick {
             Brick::Nic(ref b) => b.brick,
             Brick::Hub(ref b) => b.brick,
             Brick::Vhost(ref b) => b.brick,
+            Brick::Queue(ref b) => b.brick,
         }
     }
 
@@ -160,38 +169,21 @@ impl<'a> Brick {
     }
 
     pub fn svg(&mut self) -> Result<String, Error> {
-        let mut err = Error::new();
-        let dot = self.dot();
-        let process = match Command::new("dot")
-                  .stdin(Stdio::piped())
-                  .stdout(Stdio::piped())
-                  .arg("-Tsvg")
-                  .spawn() {
-            Err(e) => {
-                err.set(format!("cannot spawn dot: {}", e.description()));
-                return Err(err);
-            }
-            Ok(p) => p,
-        };
+        dot_to_svg(&self.dot())
+    }
 
-        match process.stdin.unwrap().write_all(dot.as_bytes()) {
-            Err(e) => {
-                err.set(format!("cannot write dot stdin: {}", e.description()));
-                return Err(err);
-            }
-            Ok(_) => {}
-        };
+    // Packets which went through a side of the brick
+    pub fn pkts_count(&self, side: Side) -> u64 {
+        unsafe { pg_brick_pkts_count_get(self.get_brick(), side.into()) }
+    }
 
-        let mut out = String::new();
-        match process.stdout.unwrap().read_to_string(&mut out) {
-            Err(e) => {
-                err.set(format!("cannot read dot stdout: {}", e.description()));
-                return Err(err);
-            }
-            Ok(_) => {}
+    // Links packetgraph holds on this brick, on both sides. packetgraph
+    // counts one reference for the owner plus one per link.
+    pub fn links_count(&self) -> usize {
+        match unsafe { pg_brick_refcount(self.get_brick()) } {
+            n if n > 1 => (n - 1) as usize,
+            _ => 0,
         }
-
-        return Ok(out);
     }
 
     // TODO: use macro ?
@@ -243,6 +235,81 @@ impl<'a> Brick {
             _ => None,
         }
     }
+
+    pub fn queue(&mut self) -> Option<&mut Queue> {
+        match *self {
+            Brick::Queue(ref mut b) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+// Render a DOT graph with graphviz's dot
+pub fn dot_to_svg(dot: &str) -> Result<String, Error> {
+    let mut err = Error::new();
+    let mut process = match Command::new("dot")
+              .stdin(Stdio::piped())
+              .stdout(Stdio::piped())
+              .stderr(Stdio::null())
+              .arg("-Tsvg")
+              .spawn() {
+        Err(ref e) if e.kind() == ErrorKind::NotFound => {
+            err.set("cannot find dot, is graphviz installed ?");
+            return Err(err);
+        }
+        Err(e) => {
+            err.set(format!("cannot spawn dot: {}", e.description()));
+            return Err(err);
+        }
+        Ok(p) => p,
+    };
+
+    // Feed and read dot in threads so a stuck dot cannot block us
+    let mut stdin = process.stdin.take().unwrap();
+    let dot = String::from(dot);
+    let writer = thread::spawn(move || stdin.write_all(dot.as_bytes()));
+    let mut stdout = process.stdout.take().unwrap();
+    let reader = thread::spawn(move || {
+        let mut out = String::new();
+        stdout.read_to_string(&mut out).map(|_| out)
+    });
+
+    let start = Instant::now();
+    let status = loop {
+        match process.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if start.elapsed() < DOT_TIMEOUT => thread::sleep(Duration::from_millis(10)),
+            Ok(None) => {
+                let _ = process.kill();
+                let _ = process.wait();
+                err.set(format!("dot did not finish within {} seconds", DOT_TIMEOUT.as_secs()));
+                return Err(err);
+            }
+            Err(e) => {
+                err.set(format!("cannot wait for dot: {}", e.description()));
+                return Err(err);
+            }
+        }
+    };
+
+    if let Err(e) = writer.join().unwrap() {
+        err.set(format!("cannot write dot stdin: {}", e.description()));
+        return Err(err);
+    }
+    let out = match reader.join().unwrap() {
+        Err(e) => {
+            err.set(format!("cannot read dot stdout: {}", e.description()));
+            return Err(err);
+        }
+        Ok(out) => out,
+    };
+    match status.success() {
+        true => Ok(out),
+        false => {
+            err.set(format!("dot failed: {}", status));
+            Err(err)
+        }
+    }
 }
 
 #[cfg(test)]
@@ -263,6 +330,7 @@ mod tests {
         tap1.link(&mut nop1).unwrap();
         nop1.link(&mut nop2).unwrap();
         nop2.link(&mut tap2).unwrap();
+        assert_eq!(nop1.links_count(), 2);
         assert!(nop2.link(&mut tap2).is_err());
         assert!(nop1.unlink_from(&mut tap2).is_err());
         assert!(nop2.unlink_from(&mut nop1).is_err());
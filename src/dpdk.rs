@@ -0,0 +1,149 @@
+/* Copyright 2017 Outscale SAS
+ *
+ * This file is part of Pg, a Rust Wrapper for packetgraph C library.
+ *
+ * Pg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 3 as published
+ * by the Free Software Foundation.
+ *
+ * Packetgraph is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Packetgraph.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// DPDK ethdev calls packetgraph does not wrap. DPDK is linked in
+// libpacketgraph, so these symbols come with it. Port ids are u16 as in
+// pg_nic_new_by_id.
+
+use std::ffi::{CStr, CString};
+use std::mem;
+use libc::{c_char, c_int, c_void};
+use packetgraph_sys::ether_addr;
+
+// RTE_ETH_NAME_MAX_LEN
+const NAME_MAX_LEN: usize = 64;
+
+// struct rte_eth_link, `bits` holds link_duplex:1, link_autoneg:1 and
+// link_status:1 from the lowest bit
+#[repr(C, align(8))]
+struct RteEthLink {
+    link_speed: u32,
+    bits: u16,
+}
+
+// struct rte_eth_dev_info starts with the device pointer then driver_name in
+// every DPDK release packetgraph builds with, the rest of its layout moves
+// around. It is only read through DevInfo, large enough for any of them.
+#[repr(C)]
+struct DevInfo {
+    device: *const c_void,
+    driver_name: *const c_char,
+    rest: [u64; 126],
+}
+
+extern "C" {
+    fn rte_eth_find_next(port_id: u16) -> u16;
+    fn rte_eth_dev_is_valid_port(port_id: u16) -> c_int;
+    fn rte_eth_dev_info_get(port_id: u16, dev_info: *mut DevInfo);
+    fn rte_eth_dev_get_name_by_port(port_id: u16, name: *mut c_char) -> c_int;
+    fn rte_eth_dev_socket_id(port_id: u16) -> c_int;
+    fn rte_eth_macaddr_get(port_id: u16, mac_addr: *mut ether_addr);
+    fn rte_eth_dev_get_port_by_name(name: *const c_char, port_id: *mut u16) -> c_int;
+    fn rte_eth_link_get_nowait(port_id: u16, link: *mut RteEthLink);
+}
+
+pub struct Link {
+    // Mbps
+    pub speed: u32,
+    pub full_duplex: bool,
+    pub autoneg: bool,
+    pub up: bool,
+}
+
+// Attached ports. Ids are not contiguous once a device is detached.
+// rte_eth_find_next returns RTE_MAX_ETHPORTS, a build option, past the last
+// port, so the walk stops on the first invalid id.
+pub fn ports() -> Vec<u16> {
+    let mut ports = Vec::new();
+    let mut p = unsafe { rte_eth_find_next(0) };
+    while unsafe { rte_eth_dev_is_valid_port(p) } != 0 {
+        ports.push(p);
+        match p.checked_add(1) {
+            Some(next) => p = unsafe { rte_eth_find_next(next) },
+            None => break,
+        }
+    }
+    ports
+}
+
+// PCI address or vdev name of the device behind `port`
+pub fn name(port: u16) -> Option<String> {
+    let mut name = [0 as c_char; NAME_MAX_LEN];
+    match unsafe { rte_eth_dev_get_name_by_port(port, name.as_mut_ptr()) } {
+        0 => Some(unsafe { CStr::from_ptr(name.as_ptr()) }.to_string_lossy().into_owned()),
+        _ => None,
+    }
+}
+
+// Name of the PMD driving `port`, like "net_ixgbe" or "net_ring"
+pub fn driver(port: u16) -> Option<String> {
+    let mut info: DevInfo = unsafe { mem::zeroed() };
+    unsafe {
+        rte_eth_dev_info_get(port, &mut info);
+    }
+    match info.driver_name.is_null() {
+        true => None,
+        false => {
+            Some(unsafe { CStr::from_ptr(info.driver_name) }.to_string_lossy().into_owned())
+        }
+    }
+}
+
+// NUMA socket of the device, None if unknown
+pub fn socket(port: u16) -> Option<u32> {
+    match unsafe { rte_eth_dev_socket_id(port) } {
+        s if s >= 0 => Some(s as u32),
+        _ => None,
+    }
+}
+
+pub fn mac(port: u16) -> [u8; 6] {
+    let mut addr: ether_addr = unsafe { mem::zeroed() };
+    unsafe {
+        rte_eth_macaddr_get(port, &mut addr);
+    }
+    addr.addr_bytes
+}
+
+// Port of an attached device, `name` is a PCI address or a vdev name
+pub fn port_by_name(name: &str) -> Option<u16> {
+    let cname = match CString::new(name) {
+        Ok(n) => n,
+        Err(_) => return None,
+    };
+    let mut port: u16 = 0;
+    match unsafe { rte_eth_dev_get_port_by_name(cname.as_ptr(), &mut port) } {
+        0 => Some(port),
+        _ => None,
+    }
+}
+
+pub fn link(port: u16) -> Link {
+    let mut l = RteEthLink {
+        link_speed: 0,
+        bits: 0,
+    };
+    unsafe {
+        rte_eth_link_get_nowait(port, &mut l);
+    }
+    Link {
+        speed: l.link_speed,
+        full_duplex: l.bits & 1 != 0,
+        autoneg: l.bits & 2 != 0,
+        up: l.bits & 4 != 0,
+    }
+}
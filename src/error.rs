@@ -38,6 +38,15 @@ impl Error {
         self.comment = comment.into();
     }
 
+    // Prefix error comment, to tell where the error comes from
+    pub fn context<S: Into<String>>(mut self, context: S) -> Error {
+        self.comment = match self.comment.is_empty() {
+            true => context.into(),
+            false => format!("{}: {}", context.into(), self.comment),
+        };
+        self
+    }
+
     pub fn is_set(&mut self) -> bool {
         unsafe {
             return pg_error_is_set(&mut self.ptr) || self.comment.len() > 0;
@@ -47,7 +56,10 @@ impl Error {
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Packetgraph error: [TODO]")
+        match self.comment.is_empty() {
+            true => write!(f, "Packetgraph error: [TODO]"),
+            false => write!(f, "Packetgraph error: {}", self.comment),
+        }
     }
 }
 
@@ -30,6 +30,8 @@ lazy_static! {
 pub struct Firewall {
     pub brick: *mut pg_brick,
     pub name: String,
+    // Rules added since last flush
+    rules: Vec<(String, Side)>,
 }
 
 impl Firewall {
@@ -43,6 +45,7 @@ impl Firewall {
             Firewall {
                 brick: pg_firewall_new(cname.as_ptr(), PG_NONE as u64, &mut error.ptr),
                 name: name,
+                rules: Vec::new(),
             }
         }
     }
@@ -54,7 +57,8 @@ impl Firewall {
     pub fn rule_add<S: Into<String>>(&mut self, rule: S, side: Side) -> Result<(), Error> {
         let mut m = NPF_USE.lock().unwrap();
         let mut error = Error::new();
-        let filter = CString::new(rule.into().as_str()).unwrap();
+        let rule = rule.into();
+        let filter = CString::new(rule.as_str()).unwrap();
         unsafe {
             pg_firewall_rule_add(self.brick, filter.as_ptr(), side.into(), 1, &mut error.ptr);
         }
@@ -62,7 +66,10 @@ impl Firewall {
         *m = true;
         match error.is_set() {
             true => Err(error),
-            false => Ok(()),
+            false => {
+                self.rules.push((rule, side));
+                Ok(())
+            }
         }
     }
 
@@ -72,6 +79,11 @@ impl Firewall {
         unsafe {
             pg_firewall_rule_flush(self.brick);
         }
+        self.rules.clear();
+    }
+
+    pub fn rules(&self) -> &[(String, Side)] {
+        &self.rules
     }
 
     pub fn reload(&mut self) -> Result<(), Error> {
@@ -119,7 +131,9 @@ mod tests {
         fw.rule_add("src host 10::2", Side::East).unwrap();
         fw.reload().unwrap();
         fw.reload().unwrap();
+        assert_eq!(fw.rules().len(), 2);
         fw.flush();
         fw.reload().unwrap();
+        assert!(fw.rules().is_empty());
     }
 }
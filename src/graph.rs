@@ -15,13 +15,73 @@
  * along with Packetgraph.  If not, see <http://www.gnu.org/licenses/>.
  */
 
+pub mod config;
+pub mod export;
+pub mod plan;
+pub mod traverse;
+pub mod validate;
+
 use std::collections::HashMap;
+use std::error;
+use std::fmt;
 use brick::Brick;
 use error::Error;
 
+// Link from west brick's east side to east brick's west side
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Edge {
+    pub west: String,
+    pub east: String,
+}
+
+// Graph::add() failure, the rejected brick is given back
+pub struct AddError {
+    pub brick: Brick,
+    pub error: Error,
+}
+
+impl fmt::Debug for AddError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AddError {{ brick: {}, error: {:?} }}", self.brick.name(), self.error)
+    }
+}
+
+impl fmt::Display for AddError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl error::Error for AddError {}
+
+impl From<AddError> for Error {
+    fn from(e: AddError) -> Error {
+        e.error
+    }
+}
+
 pub struct Graph {
     pub name: String,
     pub bricks: HashMap<String, Brick>,
+    // packetgraph doesn't give access to brick edges, so links made through
+    // the graph are kept here in link order
+    links: Vec<Edge>,
+}
+
+// A graph which can be moved to another thread, see Graph::into_send()
+pub struct SendGraph {
+    graph: Graph,
+}
+
+// Polling a brick runs all bricks linked to it, so a brick can only move
+// with everything it is linked to. A SendGraph has no link going out of it,
+// and packetgraph keeps no per thread state, so the whole graph can move.
+unsafe impl Send for SendGraph {}
+
+impl SendGraph {
+    pub fn into_graph(self) -> Graph {
+        self.graph
+    }
 }
 
 impl Graph {
@@ -29,6 +89,7 @@ impl Graph {
         Graph {
             name: name.into(),
             bricks: HashMap::new(),
+            links: Vec::new(),
         }
     }
 
@@ -40,37 +101,231 @@ impl Graph {
             .collect::<Vec<Result<usize, Error>>>()
     }
 
-    pub fn add(&mut self, brick: Brick) -> &mut Graph {
-        self.bricks.insert(brick.name(), brick);
-        return self;
+    // Poll until `idle_polls` rounds in a row did not get any packet, useful
+    // to replay a pcap file until its end. Returns the number of polled
+    // packets or the first poll error.
+    pub fn poll_until_idle(&mut self, idle_polls: usize) -> Result<usize, Error> {
+        let mut total = 0;
+        let mut idle = 0;
+        while idle < idle_polls {
+            let mut n = 0;
+            for r in self.poll() {
+                n += r?;
+            }
+            total += n;
+            idle = match n {
+                0 => idle + 1,
+                _ => 0,
+            };
+        }
+        Ok(total)
+    }
+
+    // Bricks are identified by their name, adding a brick whose name is
+    // already used fails and gives the brick back.
+    pub fn add(&mut self, brick: Brick) -> Result<&mut Graph, AddError> {
+        let name = brick.name();
+        if self.bricks.contains_key(&name) {
+            let mut e = Error::new();
+            e.set(format!("brick {} already exists in graph {}", name, self.name));
+            return Err(AddError {
+                brick: brick,
+                error: e,
+            });
+        }
+        self.bricks.insert(name, brick);
+        Ok(self)
+    }
+
+    // Unlink the brick from all its neighbours and give it back
+    pub fn remove(&mut self, name: &str) -> Option<Brick> {
+        let b = self.bricks.remove(name).map(|mut b| {
+            b.unlink();
+            b
+        });
+        self.links.retain(|e| e.west != name && e.east != name);
+        b
+    }
+
+    // Move all bricks of `other` in this graph, links between them are kept.
+    // If a name is used in both graphs nothing is moved.
+    pub fn merge(&mut self, other: &mut Graph) -> Result<(), Error> {
+        let mut conflicts = other.bricks
+            .keys()
+            .filter(|name| self.bricks.contains_key(*name))
+            .cloned()
+            .collect::<Vec<String>>();
+        if !conflicts.is_empty() {
+            conflicts.sort();
+            let mut e = Error::new();
+            e.set(format!("cannot merge graph {} in {}, bricks already exist: {}",
+                          other.name,
+                          self.name,
+                          conflicts.join(", ")));
+            return Err(e);
+        }
+        self.bricks.extend(other.bricks.drain());
+        self.links.append(&mut other.links);
+        Ok(())
+    }
+
+    // Move bricks `names` to a new graph. Links between moved and remaining
+    // bricks are unlinked and returned. Nothing is moved if a moved brick has
+    // links the graph does not know, as they could not be cut.
+    pub fn split<S: Into<String>>(&mut self,
+                                  name: S,
+                                  names: &[&str])
+                                  -> Result<(Graph, Vec<Edge>), Error> {
+        for n in names {
+            if !self.bricks.contains_key(*n) {
+                return Err(self.unknown(n));
+            }
+        }
+        for n in names {
+            self.check_links(n)?;
+        }
+        let edges = self.edges();
+        let cut = edges.iter()
+            .filter(|e| names.contains(&e.west.as_str()) != names.contains(&e.east.as_str()))
+            .cloned()
+            .collect::<Vec<Edge>>();
+        for e in &cut {
+            if let Err(err) = self.unlink(&e.west, &e.east) {
+                self.relink(&edges);
+                return Err(err);
+            }
+        }
+        let mut g = Graph::new(name);
+        for n in names {
+            if let Some(b) = self.bricks.remove(*n) {
+                g.bricks.insert(String::from(*n), b);
+            }
+        }
+        let (moved, kept) = self.links
+            .drain(..)
+            .partition(|e| names.contains(&e.west.as_str()));
+        g.links = moved;
+        self.links = kept;
+        Ok((g, cut))
+    }
+
+    // Links between bricks of this graph, in link order. Only links made
+    // through the graph are known: bricks linked with Brick::link() are not
+    // tracked.
+    pub fn edges(&self) -> Vec<Edge> {
+        self.links.clone()
+    }
+
+    pub fn link(&mut self, west: &str, east: &str) -> Result<(), Error> {
+        self.with_pair(west, east, |w, e| w.link(e))?;
+        self.links.push(Edge {
+            west: String::from(west),
+            east: String::from(east),
+        });
+        Ok(())
+    }
+
+    pub fn unlink(&mut self, west: &str, east: &str) -> Result<(), Error> {
+        self.with_pair(west, east, |w, e| w.unlink_from(e))?;
+        if let Some(i) = self.links.iter().position(|e| e.west == west && e.east == east) {
+            self.links.remove(i);
+        }
+        Ok(())
+    }
+
+    // Unlink brick from all its neighbours, in this graph or not
+    pub fn unlink_all(&mut self, name: &str) -> Result<(), Error> {
+        match self.bricks.get_mut(name) {
+            Some(b) => b.unlink(),
+            None => return Err(self.unknown(name)),
+        }
+        self.links.retain(|e| e.west != name && e.east != name);
+        Ok(())
     }
 
-    pub fn dot(&mut self) -> Result<String, Error> {
-        match self.bricks.iter_mut().next() {
-            Some((_, b)) => Ok(b.dot()),
-            None => {
-                let mut e = Error::new();
-                e.set("no brick available in graph");
-                return Err(e);
+    // Check that no brick of the graph is linked to a brick outside of it,
+    // which is what packetgraph says when the graph knows all links.
+    pub fn check_closed(&self) -> Result<(), Error> {
+        let mut e = Error::new();
+        for l in &self.links {
+            for name in &[&l.west, &l.east] {
+                if !self.bricks.contains_key(*name) {
+                    e.set(format!("graph {} is linked to {} which is not in it", self.name, name));
+                    return Err(e);
+                }
             }
         }
+        for name in self.bricks.keys() {
+            self.check_links(name)?;
+        }
+        Ok(())
+    }
+
+    // Check that packetgraph and the graph agree on the links of `name`
+    fn check_links(&self, name: &str) -> Result<(), Error> {
+        let known = self.links.iter().filter(|l| l.west == name || l.east == name).count();
+        if self.bricks[name].links_count() != known {
+            let mut e = Error::new();
+            e.set(format!("brick {} of graph {} has links the graph does not know",
+                          name,
+                          self.name));
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // Make the graph movable to another thread if it is closed (see
+    // check_closed()), or give it back.
+    pub fn into_send(self) -> Result<SendGraph, (Graph, Error)> {
+        match self.check_closed() {
+            Ok(()) => Ok(SendGraph { graph: self }),
+            Err(e) => Err((self, e)),
+        }
     }
 
-    pub fn svg(&mut self) -> Result<String, Error> {
-        match self.bricks.iter_mut().next() {
-            Some((_, b)) => b.svg(),
-            None => {
-                let mut e = Error::new();
-                e.set("no brick available in graph");
-                return Err(e);
+    // Unlink all bricks then link `edges` in this order, packetgraph gives
+    // ports in link order. Best effort, to put back links after a failed or
+    // temporary change: compare edges() to be sure. Known links already gone
+    // from packetgraph are forgotten.
+    pub fn relink(&mut self, edges: &[Edge]) {
+        for l in self.edges() {
+            let _ = self.with_pair(&l.west, &l.east, |w, e| w.unlink_from(e));
+        }
+        self.links.clear();
+        for e in edges {
+            let _ = self.link(&e.west, &e.east);
+        }
+    }
+
+    fn with_pair<F>(&mut self, west: &str, east: &str, f: F) -> Result<(), Error>
+        where F: FnOnce(&mut Brick, &mut Brick) -> Result<(), Error>
+    {
+        for name in &[west, east] {
+            if !self.bricks.contains_key(*name) {
+                return Err(self.unknown(name));
             }
         }
+        if west == east {
+            let mut e = Error::new();
+            e.set(format!("cannot link brick {} with itself", west));
+            return Err(e);
+        }
+        let mut w = self.bricks.remove(west).unwrap();
+        let ret = f(&mut w, self.bricks.get_mut(east).unwrap());
+        self.bricks.insert(String::from(west), w);
+        ret
+    }
+
+    fn unknown(&self, name: &str) -> Error {
+        let mut e = Error::new();
+        e.set(format!("no brick named {} in graph {}", name, self.name));
+        e
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Graph;
+    use super::{Graph, Edge};
     use super::super::init;
     use super::super::Side;
     use super::super::brick::Brick;
@@ -87,7 +342,7 @@ mod tests {
         tap1.link(&mut nop).unwrap();
         nop.link(&mut tap2).unwrap();
         let mut g = Graph::new("graph");
-        g.add(tap1).add(nop).add(tap2);
+        g.add(tap1).unwrap().add(nop).unwrap().add(tap2).unwrap();
         assert_eq!(g.bricks.len(), 3);
         assert_eq!(g.poll().len(), 2);
     }
@@ -101,7 +356,7 @@ mod tests {
         tap1.link(&mut nop).unwrap();
         nop.link(&mut tap2).unwrap();
         let mut g = Graph::new("graph");
-        g.add(tap1).add(nop).add(tap2);
+        g.add(tap1).unwrap().add(nop).unwrap().add(tap2).unwrap();
         g.bricks.get_mut("tap1").unwrap().poll().unwrap();
     }
 
@@ -109,9 +364,163 @@ mod tests {
     fn get_special_brick() {
         init();
         let mut g = Graph::new("graph");
-        g.add(Brick::Firewall(Firewall::new("fw")));
+        g.add(Brick::Firewall(Firewall::new("fw"))).unwrap();
         let firewall = g.bricks.get_mut("fw").unwrap().firewall().unwrap();
         firewall.rule_add("src host 10::2", Side::West).unwrap();
         firewall.reload().unwrap();
     }
+
+    #[test]
+    fn link_by_name() {
+        init();
+        let mut g = Graph::new("graph");
+        g.add(Brick::Tap(Tap::new("tap1")))
+            .unwrap()
+            .add(Brick::Nop(Nop::new("nop")))
+            .unwrap()
+            .add(Brick::Tap(Tap::new("tap2")))
+            .unwrap();
+        g.link("tap1", "nop").unwrap();
+        g.link("nop", "tap2").unwrap();
+        assert!(g.link("nop", "unknown").is_err());
+        assert!(g.link("nop", "nop").is_err());
+        assert_eq!(g.edges(),
+                   vec![Edge {
+                            west: String::from("tap1"),
+                            east: String::from("nop"),
+                        },
+                        Edge {
+                            west: String::from("nop"),
+                            east: String::from("tap2"),
+                        }]);
+        g.unlink("tap1", "nop").unwrap();
+        assert!(g.unlink("tap1", "nop").is_err());
+        assert_eq!(g.edges().len(), 1);
+        g.unlink_all("tap2").unwrap();
+        assert!(g.edges().is_empty());
+        assert!(g.unlink_all("unknown").is_err());
+    }
+
+    #[test]
+    fn add_remove() {
+        init();
+        let mut g = Graph::new("graph");
+        g.add(Brick::Tap(Tap::new("tap1")))
+            .unwrap()
+            .add(Brick::Nop(Nop::new("nop")))
+            .unwrap()
+            .add(Brick::Tap(Tap::new("tap2")))
+            .unwrap();
+        match g.add(Brick::Firewall(Firewall::new("nop"))) {
+            Err(e) => assert_eq!(e.brick.type_str(), "firewall"),
+            Ok(_) => panic!("nop added twice"),
+        }
+        assert_eq!(g.bricks.len(), 3);
+        g.link("tap1", "nop").unwrap();
+        g.link("nop", "tap2").unwrap();
+        let nop = g.remove("nop").unwrap();
+        assert!(g.remove("nop").is_none());
+        assert!(g.edges().is_empty());
+        g.link("tap1", "tap2").unwrap();
+
+        // Re-add it elsewhere
+        let mut g2 = Graph::new("graph2");
+        g2.add(nop).unwrap().add(Brick::Nop(Nop::new("nop2"))).unwrap();
+        g2.link("nop", "nop2").unwrap();
+        assert_eq!(g2.edges().len(), 1);
+    }
+
+    #[test]
+    fn merge_split() {
+        init();
+        let mut uplink = Graph::new("uplink");
+        uplink.add(Brick::Tap(Tap::new("tap")))
+            .unwrap()
+            .add(Brick::Nop(Nop::new("nop")))
+            .unwrap();
+        uplink.link("tap", "nop").unwrap();
+        let mut tenant = Graph::new("tenant");
+        tenant.add(Brick::Firewall(Firewall::new("fw")))
+            .unwrap()
+            .add(Brick::Tap(Tap::new("vm")))
+            .unwrap();
+        tenant.link("fw", "vm").unwrap();
+
+        let mut conflict = Graph::new("conflict");
+        conflict.add(Brick::Nop(Nop::new("nop"))).unwrap();
+        assert!(uplink.merge(&mut conflict).is_err());
+        assert_eq!(conflict.bricks.len(), 1);
+        assert_eq!(uplink.bricks.len(), 2);
+
+        uplink.merge(&mut tenant).unwrap();
+        assert!(tenant.bricks.is_empty());
+        assert_eq!(uplink.bricks.len(), 4);
+        uplink.link("nop", "fw").unwrap();
+        assert_eq!(uplink.edges().len(), 3);
+
+        assert!(uplink.split("tenant", &["fw", "unknown"]).is_err());
+        let (tenant, cut) = uplink.split("tenant", &["fw", "vm"]).unwrap();
+        assert_eq!(cut,
+                   vec![Edge {
+                            west: String::from("nop"),
+                            east: String::from("fw"),
+                        }]);
+        assert_eq!(uplink.edges().len(), 1);
+        assert_eq!(tenant.name, "tenant");
+        assert_eq!(tenant.edges(),
+                   vec![Edge {
+                            west: String::from("fw"),
+                            east: String::from("vm"),
+                        }]);
+        assert!(uplink.neighbours("nop", Side::East).unwrap().is_empty());
+
+        // c -> d is unlinked behind the graph's back: split fails without
+        // changing anything, relink puts back links in the same order
+        let mut g = Graph::new("g");
+        for name in &["a", "b", "c", "d"] {
+            g.add(Brick::Nop(Nop::new(*name))).unwrap();
+        }
+        g.link("a", "b").unwrap();
+        g.link("b", "c").unwrap();
+        g.link("c", "d").unwrap();
+        let edges = g.edges();
+        let mut d = g.bricks.remove("d").unwrap();
+        g.bricks.get_mut("c").unwrap().unlink_from(&mut d).unwrap();
+        g.bricks.insert(String::from("d"), d);
+        assert!(g.split("g2", &["b", "c"]).is_err());
+        assert_eq!(g.bricks.len(), 4);
+        assert_eq!(g.edges(), edges);
+        g.relink(&edges);
+        assert_eq!(g.edges(), edges);
+        assert!(g.check_closed().is_ok());
+
+        // c -> a is linked behind the graph's back, it can't be cut
+        let mut a = g.bricks.remove("a").unwrap();
+        g.bricks.get_mut("c").unwrap().link(&mut a).unwrap();
+        g.bricks.insert(String::from("a"), a);
+        assert!(g.split("g2", &["c", "d"]).is_err());
+        assert_eq!(g.bricks.len(), 4);
+        assert_eq!(g.edges(), edges);
+    }
+
+    #[test]
+    fn send() {
+        init();
+        let mut g = Graph::new("graph");
+        g.add(Brick::Nop(Nop::new("nop1"))).unwrap().add(Brick::Nop(Nop::new("nop2"))).unwrap();
+        g.link("nop1", "nop2").unwrap();
+        assert!(g.check_closed().is_ok());
+        let mut outside = Brick::Nop(Nop::new("outside"));
+        g.bricks.get_mut("nop2").unwrap().link(&mut outside).unwrap();
+        let g = match g.into_send() {
+            Err((g, _)) => g,
+            Ok(_) => panic!("graph linked to outside moved"),
+        };
+        outside.unlink();
+        let mut g = g.into_send().ok().unwrap().into_graph();
+        assert_eq!(g.edges().len(), 1);
+        // Edge to a brick taken out of the graph by hand
+        g.bricks.remove("nop1");
+        assert!(g.check_closed().is_err());
+    }
 }
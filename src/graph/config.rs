@@ -0,0 +1,507 @@
+/* Copyright 2017 Outscale SAS
+ *
+ * This file is part of Pg, a Rust Wrapper for packetgraph C library.
+ *
+ * Pg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 3 as published
+ * by the Free Software Foundation.
+ *
+ * Packetgraph is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Packetgraph.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// Graph description files, in TOML:
+//
+//     name = "my network"
+//
+//     [[brick]]
+//     type = "tap"
+//     name = "tap"
+//
+//     [[brick]]
+//     type = "switch"
+//     name = "switch"
+//     west_max = 1
+//     east_max = 3
+//     output = "west"
+//
+//     [[link]]
+//     west = "tap"
+//     east = "switch"
+//
+// or the same structure in JSON.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use toml;
+use serde_json;
+use super::{Graph, Edge};
+use super::super::Side;
+use brick::Brick;
+use error::Error;
+use nop::Nop;
+use firewall::Firewall;
+use tap::Tap;
+use switch::Switch;
+use nic::Nic;
+use hub::Hub;
+use vhost::{VhostConfig, VhostFlags};
+use queue::Queue;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GraphSpec {
+    pub name: String,
+    #[serde(default, rename = "brick")]
+    pub bricks: Vec<BrickSpec>,
+    #[serde(default, rename = "link")]
+    pub links: Vec<Edge>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum BrickSpec {
+    Nop { name: String },
+    Firewall {
+        name: String,
+        #[serde(default)]
+        rules: Vec<RuleSpec>,
+    },
+    Tap {
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ifname: Option<String>,
+    },
+    Switch {
+        name: String,
+        west_max: u32,
+        east_max: u32,
+        output: Side,
+    },
+    Hub {
+        name: String,
+        west_max: u32,
+        east_max: u32,
+    },
+    // Either a device string (PCI address, vdev) or a DPDK port id
+    Nic {
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        vdev: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        port: Option<u16>,
+    },
+    Vhost {
+        name: String,
+        #[serde(default)]
+        client: bool,
+        #[serde(default)]
+        no_reconnect: bool,
+        #[serde(default)]
+        dequeue_zero_copy: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        socket: Option<PathBuf>,
+    },
+    // Friend queues are set at run time, see Queue::friend()
+    Queue {
+        name: String,
+        #[serde(default)]
+        size: u32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleSpec {
+    pub filter: String,
+    pub side: Side,
+}
+
+impl BrickSpec {
+    pub fn name(&self) -> &str {
+        match *self {
+            BrickSpec::Nop { ref name } => name,
+            BrickSpec::Firewall { ref name, .. } => name,
+            BrickSpec::Tap { ref name, .. } => name,
+            BrickSpec::Switch { ref name, .. } => name,
+            BrickSpec::Hub { ref name, .. } => name,
+            BrickSpec::Nic { ref name, .. } => name,
+            BrickSpec::Vhost { ref name, .. } => name,
+            BrickSpec::Queue { ref name, .. } => name,
+        }
+    }
+
+    pub fn build(&self) -> Result<Brick, Error> {
+        Ok(match *self {
+            BrickSpec::Nop { ref name } => Brick::Nop(Nop::new(name.as_str())),
+            BrickSpec::Firewall { ref name, ref rules } => {
+                let mut fw = Firewall::new(name.as_str());
+                for (i, r) in rules.iter().enumerate() {
+                    fw.rule_add(r.filter.as_str(), r.side)
+                        .map_err(|e| e.context(format!("rule #{} ({})", i, r.filter)))?;
+                }
+                fw.reload()?;
+                Brick::Firewall(fw)
+            }
+            BrickSpec::Tap { ref name, ref ifname } => {
+                match *ifname {
+                    Some(ref ifname) => Brick::Tap(Tap::with_ifname(name.as_str(), ifname)?),
+                    None => Brick::Tap(Tap::new(name.as_str())),
+                }
+            }
+            BrickSpec::Switch { ref name, west_max, east_max, output } => {
+                Brick::Switch(Switch::new(name.as_str(), west_max, east_max, output))
+            }
+            BrickSpec::Hub { ref name, west_max, east_max } => {
+                Brick::Hub(Hub::new(name.as_str(), west_max, east_max))
+            }
+            BrickSpec::Nic { ref name, ref vdev, port } => {
+                match (vdev.as_ref(), port) {
+                    (Some(vdev), None) => Brick::Nic(Nic::new(name.as_str(), vdev)?),
+                    (None, Some(port)) => Brick::Nic(Nic::new_port(name.as_str(), port)?),
+                    _ => {
+                        let mut e = Error::new();
+                        e.set("nic needs either vdev or port");
+                        return Err(e);
+                    }
+                }
+            }
+            BrickSpec::Vhost { ref name,
+                               client,
+                               no_reconnect,
+                               dequeue_zero_copy,
+                               ref socket } => {
+                let mut config = VhostConfig::new(name.as_str())
+                    .client(client)
+                    .reconnect(!no_reconnect)
+                    .dequeue_zero_copy(dequeue_zero_copy);
+                if let Some(ref socket) = *socket {
+                    config = config.socket_path(socket.clone());
+                }
+                Brick::Vhost(config.build()?)
+            }
+            BrickSpec::Queue { ref name, size } => Brick::Queue(Queue::new(name.as_str(), size)?),
+        })
+    }
+
+    pub fn from_brick(brick: &Brick) -> BrickSpec {
+        let name = brick.name();
+        match *brick {
+            Brick::Nop(_) => BrickSpec::Nop { name: name },
+            Brick::Firewall(ref fw) => {
+                BrickSpec::Firewall {
+                    name: name,
+                    rules: fw.rules()
+                        .iter()
+                        .map(|&(ref filter, side)| {
+                            RuleSpec {
+                                filter: filter.clone(),
+                                side: side,
+                            }
+                        })
+                        .collect(),
+                }
+            }
+            Brick::Tap(ref tap) => {
+                BrickSpec::Tap {
+                    name: name,
+                    ifname: Some(tap.ifname()),
+                }
+            }
+            Brick::Switch(ref sw) => {
+                BrickSpec::Switch {
+                    name: name,
+                    west_max: sw.west_max(),
+                    east_max: sw.east_max(),
+                    output: sw.output(),
+                }
+            }
+            Brick::Hub(ref hub) => {
+                BrickSpec::Hub {
+                    name: name,
+                    west_max: hub.west_max(),
+                    east_max: hub.east_max(),
+                }
+            }
+            Brick::Nic(ref nic) => {
+                // The port of a vdev nic is given by DPDK, build() takes one
+                // or the other
+                let vdev = nic.vdev().map(String::from);
+                let port = match vdev {
+                    Some(_) => None,
+                    None => nic.port_id(),
+                };
+                BrickSpec::Nic {
+                    name: name,
+                    vdev: vdev,
+                    port: port,
+                }
+            }
+            Brick::Vhost(ref vhost) => {
+                let flags = vhost.flags();
+                BrickSpec::Vhost {
+                    name: name,
+                    client: flags.contains(VhostFlags::CLIENT),
+                    no_reconnect: flags.contains(VhostFlags::NO_RECONNECT),
+                    dequeue_zero_copy: flags.contains(VhostFlags::DEQUEUE_ZERO_COPY),
+                    socket: vhost.socket_path().map(|p| p.to_path_buf()),
+                }
+            }
+            Brick::Queue(ref queue) => {
+                BrickSpec::Queue {
+                    name: name,
+                    size: queue.size(),
+                }
+            }
+        }
+    }
+}
+
+impl GraphSpec {
+    pub fn from_toml(s: &str) -> Result<GraphSpec, Error> {
+        toml::from_str(s).map_err(|e| {
+            let mut error = Error::new();
+            error.set(format!("invalid graph description: {}", e));
+            error
+        })
+    }
+
+    pub fn from_json(s: &str) -> Result<GraphSpec, Error> {
+        serde_json::from_str(s).map_err(|e| {
+            let mut error = Error::new();
+            error.set(format!("invalid graph description: {}", e));
+            error
+        })
+    }
+
+    pub fn to_toml(&self) -> Result<String, Error> {
+        toml::to_string(self).map_err(|e| {
+            let mut error = Error::new();
+            error.set(format!("cannot write graph description: {}", e));
+            error
+        })
+    }
+
+    pub fn to_json(&self) -> Result<String, Error> {
+        serde_json::to_string_pretty(self).map_err(|e| {
+            let mut error = Error::new();
+            error.set(format!("cannot write graph description: {}", e));
+            error
+        })
+    }
+
+    // Describe a live graph, bricks sorted by name and links in link order
+    pub fn from_graph(graph: &Graph) -> GraphSpec {
+        let links = graph.edges();
+        let mut bricks = graph.bricks
+            .values()
+            .map(BrickSpec::from_brick)
+            .collect::<Vec<BrickSpec>>();
+        bricks.sort_by(|a, b| a.name().cmp(b.name()));
+        GraphSpec {
+            name: graph.name.clone(),
+            bricks: bricks,
+            links: links,
+        }
+    }
+
+    // Check names used by bricks and links
+    pub fn check(&self) -> Result<(), Error> {
+        let mut names = HashSet::new();
+        for (i, b) in self.bricks.iter().enumerate() {
+            if !names.insert(b.name()) {
+                let mut e = Error::new();
+                e.set(format!("brick #{} ({}): name already used", i, b.name()));
+                return Err(e);
+            }
+        }
+        for (i, l) in self.links.iter().enumerate() {
+            for name in &[&l.west, &l.east] {
+                if !names.contains(name.as_str()) {
+                    let mut e = Error::new();
+                    e.set(format!("link #{} ({} -> {}): unknown brick {}", i, l.west, l.east, name));
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn build(&self) -> Result<Graph, Error> {
+        self.check()?;
+        let mut graph = Graph::new(self.name.as_str());
+        for (i, b) in self.bricks.iter().enumerate() {
+            let brick = b.build().map_err(|e| e.context(format!("brick #{} ({})", i, b.name())))?;
+            graph.add(brick)?;
+        }
+        for (i, l) in self.links.iter().enumerate() {
+            graph.link(&l.west, &l.east)
+                .map_err(|e| e.context(format!("link #{} ({} -> {})", i, l.west, l.east)))?;
+        }
+        Ok(graph)
+    }
+}
+
+enum Format {
+    Toml,
+    Json,
+}
+
+fn format(path: &Path) -> Result<Format, Error> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => Ok(Format::Toml),
+        Some("json") => Ok(Format::Json),
+        _ => {
+            let mut e = Error::new();
+            e.set(format!("{}: unknown graph description format, use .toml or .json",
+                          path.display()));
+            Err(e)
+        }
+    }
+}
+
+// Build a graph from a .toml or .json description file
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Graph, Error> {
+    let path = path.as_ref();
+    let format = format(path)?;
+    let mut content = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut content))
+        .map_err(|e| {
+            let mut error = Error::new();
+            error.set(format!("cannot read {}: {}", path.display(), e));
+            error
+        })?;
+    let spec = match format {
+        Format::Toml => GraphSpec::from_toml(&content),
+        Format::Json => GraphSpec::from_json(&content),
+    };
+    spec.and_then(|s| s.build()).map_err(|e| e.context(path.display().to_string()))
+}
+
+// Write the description of a live graph to a .toml or .json file
+pub fn save<P: AsRef<Path>>(graph: &Graph, path: P) -> Result<(), Error> {
+    let path = path.as_ref();
+    let spec = GraphSpec::from_graph(graph);
+    let content = match format(path)? {
+        Format::Toml => spec.to_toml()?,
+        Format::Json => spec.to_json()?,
+    };
+    File::create(path)
+        .and_then(|mut f| f.write_all(content.as_bytes()))
+        .map_err(|e| {
+            let mut error = Error::new();
+            error.set(format!("cannot write {}: {}", path.display(), e));
+            error
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::init;
+    use std::env;
+
+    const DESCRIPTION: &str = r#"
+name = "config"
+
+[[brick]]
+type = "nop"
+name = "nop1"
+
+[[brick]]
+type = "switch"
+name = "sw"
+west_max = 2
+east_max = 2
+output = "west"
+
+[[brick]]
+type = "firewall"
+name = "fw"
+
+[[brick.rules]]
+filter = "src host 10::1"
+side = "west"
+
+[[brick]]
+type = "hub"
+name = "hub"
+west_max = 1
+east_max = 1
+
+[[link]]
+west = "nop1"
+east = "sw"
+
+[[link]]
+west = "sw"
+east = "fw"
+
+[[link]]
+west = "fw"
+east = "hub"
+"#;
+
+    #[test]
+    fn load_save() {
+        init();
+        let spec = GraphSpec::from_toml(DESCRIPTION).unwrap();
+        let mut g = spec.build().unwrap();
+        assert_eq!(g.bricks.len(), 4);
+        assert_eq!(g.edges().len(), 3);
+        assert_eq!(g.bricks.get_mut("fw").unwrap().firewall().unwrap().rules().len(), 1);
+
+        let mut saved = GraphSpec::from_graph(&g);
+        assert_eq!(saved.links, spec.links);
+        saved.bricks.sort_by(|a, b| a.name().cmp(b.name()));
+        let mut bricks = spec.bricks.clone();
+        bricks.sort_by(|a, b| a.name().cmp(b.name()));
+        assert_eq!(saved.bricks, bricks);
+        assert_eq!(GraphSpec::from_toml(&saved.to_toml().unwrap()).unwrap(), saved);
+        assert_eq!(GraphSpec::from_json(&saved.to_json().unwrap()).unwrap(), saved);
+
+        let path = env::temp_dir().join("pg_graph_config.json");
+        save(&g, &path).unwrap();
+        drop(g);
+        assert_eq!(load(&path).unwrap().edges().len(), 3);
+        assert!(save(&Graph::new("g"), "graph.yaml").is_err());
+
+        let mut g = Graph::new("nic");
+        g.add(Brick::Nic(Nic::new("nic", "eth_ring4").unwrap())).unwrap();
+        let saved = GraphSpec::from_graph(&g);
+        assert_eq!(saved.bricks,
+                   vec![BrickSpec::Nic {
+                            name: String::from("nic"),
+                            vdev: Some(String::from("eth_ring4")),
+                            port: None,
+                        }]);
+        assert_eq!(GraphSpec::from_toml(&saved.to_toml().unwrap()).unwrap(), saved);
+        assert!(g.plan(&saved).is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        init();
+        let e = GraphSpec::from_toml(&DESCRIPTION.replace("east = \"hub\"", "east = \"hubb\""))
+            .unwrap()
+            .build()
+            .err()
+            .unwrap();
+        assert!(format!("{}", e).contains("link #2 (fw -> hubb)"));
+
+        let e = GraphSpec::from_toml(&DESCRIPTION.replace("src host 10::1", "invalid rule"))
+            .unwrap()
+            .build()
+            .err()
+            .unwrap();
+        assert!(format!("{}", e).contains("brick #2 (fw): rule #0 (invalid rule)"));
+
+        assert!(GraphSpec::from_toml("name = \"g\"\n[[brick]]\ntype = \"unknown\"").is_err());
+    }
+}
@@ -0,0 +1,231 @@
+/* Copyright 2017 Outscale SAS
+ *
+ * This file is part of Pg, a Rust Wrapper for packetgraph C library.
+ *
+ * Pg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 3 as published
+ * by the Free Software Foundation.
+ *
+ * Packetgraph is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Packetgraph.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// Render graph topology without relying on packetgraph's per brick DOT.
+// Bricks are sorted by name and links kept in link order so the output only
+// changes when the graph does.
+
+use std::collections::HashMap;
+use serde_json;
+use super::Graph;
+use brick::{self, Brick};
+use error::Error;
+use Side;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Node {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub pollable: bool,
+    // Packets counted on each side
+    pub west_pkts: u64,
+    pub east_pkts: u64,
+}
+
+// Link from west brick's east side to east brick's west side. `west_order`
+// and `east_order` number links in link order on each side of a brick, they
+// are not packetgraph port indices which are not exported.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Link {
+    pub west: String,
+    pub west_order: usize,
+    pub east: String,
+    pub east_order: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Topology {
+    pub name: String,
+    pub bricks: Vec<Node>,
+    pub links: Vec<Link>,
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl Graph {
+    pub fn topology(&self) -> Topology {
+        let mut bricks = self.bricks.iter().collect::<Vec<(&String, &Brick)>>();
+        bricks.sort_by(|a, b| a.0.cmp(b.0));
+        let mut east_links = HashMap::new();
+        let mut west_links = HashMap::new();
+        let mut links = Vec::new();
+        for e in self.edges() {
+            let west_order = {
+                let n = east_links.entry(e.west.clone()).or_insert(0);
+                *n += 1;
+                *n - 1
+            };
+            let east_order = {
+                let n = west_links.entry(e.east.clone()).or_insert(0);
+                *n += 1;
+                *n - 1
+            };
+            links.push(Link {
+                west: e.west,
+                west_order: west_order,
+                east: e.east,
+                east_order: east_order,
+            });
+        }
+
+        Topology {
+            name: self.name.clone(),
+            bricks: bricks.iter()
+                .map(|&(name, b)| {
+                    Node {
+                        name: name.clone(),
+                        kind: String::from(b.type_str()),
+                        pollable: b.pollable(),
+                        west_pkts: b.pkts_count(Side::West),
+                        east_pkts: b.pkts_count(Side::East),
+                    }
+                })
+                .collect(),
+            links: links,
+        }
+    }
+
+    pub fn dot(&self) -> String {
+        let t = self.topology();
+        let mut out = format!("digraph {} {{\n  rankdir=LR;\n", quote(&t.name));
+        for b in &t.bricks {
+            let label = format!("{}\n{}\nwest: {} pkts, east: {} pkts",
+                                b.name,
+                                b.kind,
+                                b.west_pkts,
+                                b.east_pkts);
+            let shape = match b.pollable {
+                true => "box",
+                false => "ellipse",
+            };
+            out += &format!("  {} [shape={}, label={}];\n",
+                            quote(&b.name),
+                            shape,
+                            quote(&label).replace('\n', "\\n"));
+        }
+        for l in &t.links {
+            out += &format!("  {} -> {} [taillabel=\"east link {}\", \
+                             headlabel=\"west link {}\"];\n",
+                            quote(&l.west),
+                            quote(&l.east),
+                            l.west_order,
+                            l.east_order);
+        }
+        out + "}\n"
+    }
+
+    pub fn json(&self) -> Result<String, Error> {
+        serde_json::to_string_pretty(&self.topology()).map_err(|e| {
+            let mut err = Error::new();
+            err.set(format!("cannot serialize graph {}: {}", self.name, e));
+            err
+        })
+    }
+
+    // Mermaid ids cannot hold any character, bricks are named b0, b1...
+    pub fn mermaid(&self) -> String {
+        let t = self.topology();
+        let ids = t.bricks
+            .iter()
+            .enumerate()
+            .map(|(i, b)| (b.name.as_str(), format!("b{}", i)))
+            .collect::<HashMap<&str, String>>();
+        let mut out = String::from("graph LR\n");
+        for b in &t.bricks {
+            let label = format!("{}<br/>{}<br/>west: {} pkts, east: {} pkts",
+                                b.name,
+                                b.kind,
+                                b.west_pkts,
+                                b.east_pkts)
+                .replace('"', "#quot;");
+            let node = match b.pollable {
+                true => format!("[\"{}\"]", label),
+                false => format!("(\"{}\")", label),
+            };
+            out += &format!("  {}{}\n", ids[b.name.as_str()], node);
+        }
+        for l in &t.links {
+            out += &format!("  {} -->|\"east link {} : west link {}\"| {}\n",
+                            ids[l.west.as_str()],
+                            l.west_order,
+                            l.east_order,
+                            ids[l.east.as_str()]);
+        }
+        out
+    }
+
+    pub fn svg(&self) -> Result<String, Error> {
+        brick::dot_to_svg(&self.dot())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::init;
+    use nop::Nop;
+    use tap::Tap;
+    use switch::Switch;
+
+    #[test]
+    fn export() {
+        init();
+        let mut g = Graph::new("my \"graph\"");
+        g.add(Brick::Switch(Switch::new("sw", 1, 2, Side::West)))
+            .unwrap()
+            .add(Brick::Tap(Tap::new("tap")))
+            .unwrap()
+            .add(Brick::Nop(Nop::new("nop1")))
+            .unwrap()
+            .add(Brick::Nop(Nop::new("nop2")))
+            .unwrap();
+        g.link("tap", "sw").unwrap();
+        g.link("sw", "nop1").unwrap();
+        g.link("sw", "nop2").unwrap();
+
+        let t = g.topology();
+        assert_eq!(t.bricks.iter().map(|b| b.name.as_str()).collect::<Vec<&str>>(),
+                   vec!["nop1", "nop2", "sw", "tap"]);
+        assert_eq!(t.bricks[2].kind, "switch");
+        assert_eq!(t.links[2],
+                   Link {
+                       west: String::from("sw"),
+                       west_order: 1,
+                       east: String::from("nop2"),
+                       east_order: 0,
+                   });
+
+        let dot = g.dot();
+        assert_eq!(dot, g.dot());
+        assert!(dot.starts_with("digraph \"my \\\"graph\\\"\" {\n"));
+        assert!(dot.contains("  \"sw\" [shape=ellipse, label=\"sw\\nswitch\\nwest: 0 pkts, \
+                              east: 0 pkts\"];\n"));
+        assert!(dot.contains("  \"sw\" -> \"nop2\" [taillabel=\"east link 1\", \
+                              headlabel=\"west link 0\"];\n"));
+
+        let json: Topology = serde_json::from_str(&g.json().unwrap()).unwrap();
+        assert_eq!(json, t);
+
+        let mermaid = g.mermaid();
+        assert!(mermaid.starts_with("graph LR\n  b0(\"nop1<br/>nop<br/>"));
+        assert!(mermaid.contains("  b3[\"tap<br/>tap<br/>"));
+        assert!(mermaid.contains("  b2 -->|\"east link 1 : west link 0\"| b1\n"));
+    }
+}
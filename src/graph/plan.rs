@@ -0,0 +1,333 @@
+/* Copyright 2017 Outscale SAS
+ *
+ * This file is part of Pg, a Rust Wrapper for packetgraph C library.
+ *
+ * Pg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 3 as published
+ * by the Free Software Foundation.
+ *
+ * Packetgraph is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Packetgraph.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// Move a live graph to a desired state (GraphSpec) by only touching what
+// differs. Steps are run in this order: unlink, remove, add, reconfigure and
+// link. If a step fails, the done ones are reverted.
+
+use std::collections::HashMap;
+use std::fmt;
+use super::{Graph, Edge};
+use super::config::{GraphSpec, BrickSpec};
+use brick::Brick;
+use error::Error;
+use Side;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Unlink(Edge),
+    Remove(String),
+    Add(BrickSpec),
+    // Only firewall rules can be changed on a live brick
+    Reconfigure(BrickSpec),
+    Link(Edge),
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Plan {
+    pub steps: Vec<Step>,
+}
+
+impl Plan {
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Step::Unlink(ref e) => write!(f, "unlink {} -> {}", e.west, e.east),
+            Step::Remove(ref name) => write!(f, "remove {}", name),
+            Step::Add(ref b) => write!(f, "add {}", b.name()),
+            Step::Reconfigure(ref b) => write!(f, "reconfigure {}", b.name()),
+            Step::Link(ref e) => write!(f, "link {} -> {}", e.west, e.east),
+        }
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for step in &self.steps {
+            writeln!(f, "{}", step)?;
+        }
+        Ok(())
+    }
+}
+
+enum Change {
+    Same,
+    Reconfigure,
+    Replace,
+}
+
+fn compare(current: &BrickSpec, desired: &BrickSpec) -> Change {
+    match (current, desired) {
+        // No ifname asked, keep the kernel one
+        (&BrickSpec::Tap { .. }, &BrickSpec::Tap { ifname: None, .. }) => Change::Same,
+        (&BrickSpec::Firewall { .. }, &BrickSpec::Firewall { .. }) if current != desired => {
+            Change::Reconfigure
+        }
+        _ if current == desired => Change::Same,
+        _ => Change::Replace,
+    }
+}
+
+// How to revert a done step, links are restored apart
+enum Undo {
+    Restore(Brick),
+    Remove(String),
+    Rules(String, Vec<(String, Side)>),
+}
+
+impl Graph {
+    // Steps needed to reach `desired`, nothing is changed (dry run)
+    pub fn plan(&self, desired: &GraphSpec) -> Plan {
+        let current = GraphSpec::from_graph(self);
+        let bricks = current.bricks
+            .iter()
+            .map(|b| (b.name(), b))
+            .collect::<HashMap<&str, &BrickSpec>>();
+        let mut wanted = HashMap::new();
+        let mut added = Vec::new();
+        let mut reconfigured = Vec::new();
+        let mut removed = Vec::new();
+        for b in &desired.bricks {
+            wanted.insert(b.name(), b);
+            match bricks.get(b.name()) {
+                None => added.push(b.clone()),
+                Some(cur) => {
+                    match compare(cur, b) {
+                        Change::Same => {}
+                        Change::Reconfigure => reconfigured.push(b.clone()),
+                        Change::Replace => {
+                            removed.push(String::from(b.name()));
+                            added.push(b.clone());
+                        }
+                    }
+                }
+            }
+        }
+        for b in &current.bricks {
+            if !wanted.contains_key(b.name()) {
+                removed.push(String::from(b.name()));
+            }
+        }
+        let is_removed = |e: &Edge| removed.contains(&e.west) || removed.contains(&e.east);
+
+        let mut steps = Vec::new();
+        for e in &current.links {
+            if !desired.links.contains(e) && !is_removed(e) {
+                steps.push(Step::Unlink(e.clone()));
+            }
+        }
+        steps.extend(removed.iter().cloned().map(Step::Remove));
+        steps.extend(added.into_iter().map(Step::Add));
+        steps.extend(reconfigured.into_iter().map(Step::Reconfigure));
+        for e in &desired.links {
+            if !current.links.contains(e) || is_removed(e) {
+                steps.push(Step::Link(e.clone()));
+            }
+        }
+        Plan { steps: steps }
+    }
+
+    // Reach `desired` and return the executed plan. On failure the graph is
+    // reverted to its previous state and the failing step is reported.
+    pub fn apply(&mut self, desired: &GraphSpec) -> Result<Plan, Error> {
+        desired.check()?;
+        let plan = self.plan(desired);
+        let edges = self.edges();
+        let mut undo = Vec::new();
+        for step in &plan.steps {
+            if let Err(e) = self.run(step, &mut undo) {
+                while let Some(u) = undo.pop() {
+                    self.revert(u);
+                }
+                self.relink(&edges);
+                return Err(e.context(format!("{}", step)));
+            }
+        }
+        Ok(plan)
+    }
+
+    fn run(&mut self, step: &Step, undo: &mut Vec<Undo>) -> Result<(), Error> {
+        match *step {
+            Step::Unlink(ref e) => self.unlink(&e.west, &e.east)?,
+            Step::Remove(ref name) => {
+                if let Some(b) = self.remove(name) {
+                    undo.push(Undo::Restore(b));
+                }
+            }
+            Step::Add(ref spec) => {
+                self.add(spec.build()?)?;
+                undo.push(Undo::Remove(String::from(spec.name())));
+            }
+            Step::Reconfigure(BrickSpec::Firewall { ref name, ref rules }) => {
+                let fw = match self.bricks.get_mut(name).and_then(|b| b.firewall()) {
+                    Some(fw) => fw,
+                    None => return Err(self.unknown(name)),
+                };
+                undo.push(Undo::Rules(name.clone(), fw.rules().to_vec()));
+                fw.flush();
+                for r in rules {
+                    fw.rule_add(r.filter.as_str(), r.side)?;
+                }
+                fw.reload()?;
+            }
+            Step::Reconfigure(ref spec) => {
+                let mut e = Error::new();
+                e.set(format!("cannot reconfigure {}", spec.name()));
+                return Err(e);
+            }
+            Step::Link(ref e) => self.link(&e.west, &e.east)?,
+        }
+        Ok(())
+    }
+
+    // Best effort, errors are ignored as there is nothing left to fall back on
+    fn revert(&mut self, undo: Undo) {
+        match undo {
+            Undo::Restore(b) => {
+                let _ = self.add(b);
+            }
+            Undo::Remove(name) => {
+                self.remove(&name);
+            }
+            Undo::Rules(name, rules) => {
+                if let Some(fw) = self.bricks.get_mut(&name).and_then(|b| b.firewall()) {
+                    fw.flush();
+                    for (filter, side) in rules {
+                        let _ = fw.rule_add(filter, side);
+                    }
+                    let _ = fw.reload();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::config::RuleSpec;
+    use super::super::super::init;
+
+    fn initial() -> GraphSpec {
+        GraphSpec::from_toml(r#"
+name = "tenant"
+
+[[brick]]
+type = "switch"
+name = "sw"
+west_max = 1
+east_max = 3
+output = "west"
+
+[[brick]]
+type = "nop"
+name = "nop1"
+
+[[brick]]
+type = "nop"
+name = "nop2"
+
+[[brick]]
+type = "firewall"
+name = "fw"
+
+[[brick.rules]]
+filter = "src host 10::1"
+side = "west"
+
+[[link]]
+west = "nop1"
+east = "sw"
+
+[[link]]
+west = "sw"
+east = "nop2"
+
+[[link]]
+west = "sw"
+east = "fw"
+"#)
+            .unwrap()
+    }
+
+    fn sorted(mut spec: GraphSpec) -> GraphSpec {
+        spec.bricks.sort_by(|a, b| a.name().cmp(b.name()));
+        spec.links.sort_by(|a, b| (&a.west, &a.east).cmp(&(&b.west, &b.east)));
+        spec
+    }
+
+    #[test]
+    fn apply() {
+        init();
+        let mut g = initial().build().unwrap();
+        assert!(g.plan(&initial()).is_empty());
+
+        let mut desired = initial();
+        desired.bricks.retain(|b| b.name() != "nop2");
+        desired.links.retain(|e| e.east != "nop2");
+        desired.bricks.push(BrickSpec::Nop { name: String::from("nop3") });
+        desired.links.push(Edge {
+            west: String::from("sw"),
+            east: String::from("nop3"),
+        });
+        if let BrickSpec::Firewall { ref mut rules, .. } = desired.bricks[2] {
+            rules.push(RuleSpec {
+                filter: String::from("src host 10::2"),
+                side: Side::East,
+            });
+        }
+
+        let plan = g.plan(&desired);
+        assert_eq!(format!("{}", plan),
+                   "remove nop2\nadd nop3\nreconfigure fw\nlink sw -> nop3\n");
+        assert_eq!(g.apply(&desired).unwrap(), plan);
+        assert_eq!(sorted(GraphSpec::from_graph(&g)), sorted(desired.clone()));
+        assert!(g.plan(&desired).is_empty());
+    }
+
+    #[test]
+    fn rollback() {
+        init();
+        let mut g = initial().build().unwrap();
+        let mut desired = initial();
+        desired.bricks.retain(|b| b.name() != "nop2");
+        desired.bricks.push(BrickSpec::Nop { name: String::from("nop3") });
+        desired.bricks.push(BrickSpec::Nop { name: String::from("nop4") });
+        desired.links.retain(|e| e.east != "nop2");
+        // sw has only one west port
+        desired.links.push(Edge {
+            west: String::from("nop3"),
+            east: String::from("sw"),
+        });
+        desired.links.push(Edge {
+            west: String::from("nop4"),
+            east: String::from("nop3"),
+        });
+
+        let edges = g.edges();
+        let e = g.apply(&desired).err().unwrap();
+        assert!(format!("{}", e).contains("link nop3 -> sw"));
+        assert_eq!(sorted(GraphSpec::from_graph(&g)), sorted(initial()));
+        // Same link order, so same packetgraph ports
+        assert_eq!(g.edges(), edges);
+    }
+}
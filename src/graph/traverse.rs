@@ -0,0 +1,226 @@
+/* Copyright 2017 Outscale SAS
+ *
+ * This file is part of Pg, a Rust Wrapper for packetgraph C library.
+ *
+ * Pg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 3 as published
+ * by the Free Software Foundation.
+ *
+ * Packetgraph is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Packetgraph.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// Walk graph edges by brick name. Only bricks of the graph are visited and
+// neighbours are taken in link order, so results are stable.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use super::Graph;
+use error::Error;
+use Side;
+
+// West and east neighbours of each brick
+type Adjacency = HashMap<String, (Vec<String>, Vec<String>)>;
+
+impl Graph {
+    // Names of bricks linked to `name` on `side`, in link order. Only links
+    // made through the graph are known, see Graph::edges().
+    pub fn neighbours(&self, name: &str, side: Side) -> Result<Vec<String>, Error> {
+        if !self.bricks.contains_key(name) {
+            return Err(self.unknown(name));
+        }
+        Ok(self.links
+            .iter()
+            .filter_map(|e| match side {
+                Side::West if e.east == name => Some(e.west.clone()),
+                Side::East if e.west == name => Some(e.east.clone()),
+                _ => None,
+            })
+            .collect())
+    }
+
+    fn adjacency(&self) -> Adjacency {
+        let mut adj = self.bricks
+            .keys()
+            .map(|name| (name.clone(), (Vec::new(), Vec::new())))
+            .collect::<Adjacency>();
+        // Links to bricks taken out of `bricks` directly are not followed,
+        // see Graph::validate()
+        for e in self.edges() {
+            if !adj.contains_key(&e.west) || !adj.contains_key(&e.east) {
+                continue;
+            }
+            adj.get_mut(&e.west).unwrap().1.push(e.east.clone());
+            adj.get_mut(&e.east).unwrap().0.push(e.west);
+        }
+        adj
+    }
+
+    // Breadth first walk from `start` following links on `side` (east goes
+    // downstream), or on both sides if `side` is None. `start` comes first.
+    pub fn bfs(&self, start: &str, side: Option<Side>) -> Result<Vec<String>, Error> {
+        if !self.bricks.contains_key(start) {
+            return Err(self.unknown(start));
+        }
+        let adj = self.adjacency();
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::new();
+        let mut order = Vec::new();
+        seen.insert(String::from(start));
+        queue.push_back(String::from(start));
+        while let Some(name) = queue.pop_front() {
+            let (ref west, ref east) = adj[&name];
+            let next = match side {
+                Some(Side::West) => west.iter().collect::<Vec<&String>>(),
+                Some(Side::East) => east.iter().collect(),
+                None => west.iter().chain(east.iter()).collect(),
+            };
+            for n in next {
+                if seen.insert(n.clone()) {
+                    queue.push_back(n.clone());
+                }
+            }
+            order.push(name);
+        }
+        Ok(order)
+    }
+
+    // Shortest path from `from` to `to` whatever the link direction, both ends
+    // included. None if they are not connected.
+    pub fn path(&self, from: &str, to: &str) -> Result<Option<Vec<String>>, Error> {
+        for name in &[from, to] {
+            if !self.bricks.contains_key(*name) {
+                return Err(self.unknown(name));
+            }
+        }
+        let adj = self.adjacency();
+        let mut previous = HashMap::new();
+        let mut queue = VecDeque::new();
+        previous.insert(String::from(from), None);
+        queue.push_back(String::from(from));
+        while let Some(name) = queue.pop_front() {
+            if name == to {
+                let mut path = vec![name];
+                while let Some(Some(p)) = previous.get(path.last().unwrap()) {
+                    path.push(String::from(p));
+                }
+                path.reverse();
+                return Ok(Some(path));
+            }
+            let (ref west, ref east) = adj[&name];
+            for n in west.iter().chain(east.iter()) {
+                if !previous.contains_key(n) {
+                    previous.insert(n.clone(), Some(name.clone()));
+                    queue.push_back(n.clone());
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    // Groups of bricks linked together, names and groups are sorted
+    pub fn connected_components(&self) -> Vec<Vec<String>> {
+        let mut names = self.bricks.keys().collect::<Vec<&String>>();
+        names.sort();
+        let mut seen = HashSet::new();
+        let mut components = Vec::new();
+        for name in names {
+            if seen.contains(name) {
+                continue;
+            }
+            let mut component = self.bfs(name, None).unwrap();
+            seen.extend(component.iter().cloned());
+            component.sort();
+            components.push(component);
+        }
+        components
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::init;
+    use brick::Brick;
+    use nop::Nop;
+    use switch::Switch;
+    use hub::Hub;
+
+    // Switch and hub plug test topologies, with the hub behind nop2
+    fn topology() -> Graph {
+        let mut g = Graph::new("graph");
+        g.add(Brick::Switch(Switch::new("sw", 2, 2, Side::West))).unwrap();
+        g.add(Brick::Hub(Hub::new("hub", 2, 2))).unwrap();
+        for i in 1..8 {
+            g.add(Brick::Nop(Nop::new(format!("nop{}", i)))).unwrap();
+        }
+        g.link("sw", "nop1").unwrap();
+        g.link("sw", "nop2").unwrap();
+        g.link("nop3", "sw").unwrap();
+        g.link("nop4", "sw").unwrap();
+        g.link("nop2", "hub").unwrap();
+        g.link("hub", "nop5").unwrap();
+        g.link("hub", "nop6").unwrap();
+        g
+    }
+
+    #[test]
+    fn neighbours() {
+        init();
+        let g = topology();
+        assert_eq!(g.neighbours("sw", Side::West).unwrap(), vec!["nop3", "nop4"]);
+        assert_eq!(g.neighbours("sw", Side::East).unwrap(), vec!["nop1", "nop2"]);
+        assert_eq!(g.neighbours("hub", Side::West).unwrap(), vec!["nop2"]);
+        assert_eq!(g.neighbours("nop3", Side::East).unwrap(), vec!["sw"]);
+        assert!(g.neighbours("nop7", Side::East).unwrap().is_empty());
+        assert!(g.neighbours("unknown", Side::East).is_err());
+    }
+
+    #[test]
+    fn bfs() {
+        init();
+        let g = topology();
+        assert_eq!(g.bfs("sw", Some(Side::East)).unwrap(),
+                   vec!["sw", "nop1", "nop2", "hub", "nop5", "nop6"]);
+        assert_eq!(g.bfs("nop5", Some(Side::West)).unwrap(),
+                   vec!["nop5", "hub", "nop2", "sw", "nop3", "nop4"]);
+        assert_eq!(g.bfs("nop1", None).unwrap().len(), 8);
+        assert_eq!(g.bfs("nop7", None).unwrap(), vec!["nop7"]);
+        assert!(g.bfs("unknown", None).is_err());
+    }
+
+    #[test]
+    fn path() {
+        init();
+        let g = topology();
+        assert_eq!(g.path("nop3", "nop6").unwrap(),
+                   Some(vec![String::from("nop3"),
+                             String::from("sw"),
+                             String::from("nop2"),
+                             String::from("hub"),
+                             String::from("nop6")]));
+        assert_eq!(g.path("nop1", "nop1").unwrap(), Some(vec![String::from("nop1")]));
+        assert_eq!(g.path("nop1", "nop7").unwrap(), None);
+        assert!(g.path("nop1", "unknown").is_err());
+    }
+
+    #[test]
+    fn connected_components() {
+        init();
+        let mut g = topology();
+        assert_eq!(g.connected_components(),
+                   vec![vec!["hub", "nop1", "nop2", "nop3", "nop4", "nop5", "nop6", "sw"],
+                        vec!["nop7"]]);
+        g.unlink("nop2", "hub").unwrap();
+        assert_eq!(g.connected_components().len(), 3);
+        let nop5 = g.bricks.remove("nop5").unwrap();
+        assert_eq!(g.bfs("hub", None).unwrap(), vec!["hub", "nop6"]);
+        assert_eq!(g.path("hub", "nop6").unwrap().unwrap().len(), 2);
+        assert_eq!(g.connected_components().len(), 3);
+        drop(nop5);
+    }
+}
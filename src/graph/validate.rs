@@ -0,0 +1,223 @@
+/* Copyright 2017 Outscale SAS
+ *
+ * This file is part of Pg, a Rust Wrapper for packetgraph C library.
+ *
+ * Pg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 3 as published
+ * by the Free Software Foundation.
+ *
+ * Packetgraph is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Packetgraph.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::collections::HashMap;
+use std::fmt;
+use super::Graph;
+use brick::Brick;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub severity: Severity,
+    // Brick concerned, if any
+    pub brick: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        match self.brick {
+            Some(ref b) => write!(f, "{}: {}: {}", severity, b, self.message),
+            None => write!(f, "{}: {}", severity, self.message),
+        }
+    }
+}
+
+fn finding(severity: Severity, brick: Option<&str>, message: String) -> Finding {
+    Finding {
+        severity: severity,
+        brick: brick.map(String::from),
+        message: message,
+    }
+}
+
+// Find the root of a union-find set
+fn root<'a>(parent: &mut HashMap<&'a str, &'a str>, name: &'a str) -> &'a str {
+    let mut r = name;
+    while parent[r] != r {
+        r = parent[r];
+    }
+    parent.insert(name, r);
+    r
+}
+
+impl Graph {
+    // Check the graph topology before polling it, findings are sorted by
+    // brick name with graph wide ones first.
+    pub fn validate(&self) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        if !self.bricks.values().any(|b| b.pollable()) {
+            findings.push(finding(Severity::Error,
+                                  None,
+                                  String::from("no pollable brick, polling does nothing")));
+        }
+
+        // Cycles which don't go through a switch make packets loop
+        let links = self.edges();
+        let mut parent = self.bricks
+            .keys()
+            .map(|name| (name.as_str(), name.as_str()))
+            .collect::<HashMap<&str, &str>>();
+        for l in &links {
+            // Left behind by a brick taken out of `bricks` directly
+            let unknown = [&l.west, &l.east]
+                .iter()
+                .find(|n| !parent.contains_key(n.as_str()))
+                .cloned();
+            if let Some(name) = unknown {
+                findings.push(finding(Severity::Error,
+                                      None,
+                                      format!("link {} -> {}: {} is not in the graph",
+                                              l.west,
+                                              l.east,
+                                              name)));
+                continue;
+            }
+            let switch = |name: &str| matches!(self.bricks.get(name), Some(&Brick::Switch(_)));
+            if switch(&l.west) || switch(&l.east) {
+                continue;
+            }
+            let (w, e) = (root(&mut parent, &l.west), root(&mut parent, &l.east));
+            if w == e {
+                findings.push(finding(Severity::Error,
+                                      None,
+                                      format!("cycle through {} -> {}", l.west, l.east)));
+            } else {
+                parent.insert(w, e);
+            }
+        }
+
+        let mut bricks = self.bricks.iter().collect::<Vec<(&String, &Brick)>>();
+        bricks.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, b) in bricks {
+            let west = links.iter().filter(|l| &l.east == name).count() as u32;
+            let east = links.iter().filter(|l| &l.west == name).count() as u32;
+            // packetgraph knows about all links, the graph only about its own
+            let unknown = b.links_count().saturating_sub((west + east) as usize);
+            if unknown > 0 {
+                findings.push(finding(Severity::Error,
+                                      Some(name),
+                                      format!("{} links not made through the graph, bricks \
+                                               outside the graph may be polled",
+                                              unknown)));
+            }
+            if west + east == 0 {
+                findings.push(finding(Severity::Warning, Some(name), String::from("not linked")));
+                continue;
+            }
+            // Pollable bricks are graph ends, they only use one side
+            if !b.pollable() {
+                for &(side, n) in &[("west", west), ("east", east)] {
+                    if n == 0 {
+                        findings.push(finding(Severity::Warning,
+                                              Some(name),
+                                              format!("nothing linked on {} side", side)));
+                    }
+                }
+            }
+            let max = match *b {
+                Brick::Switch(ref s) => Some((s.west_max(), s.east_max())),
+                Brick::Hub(ref h) => Some((h.west_max(), h.east_max())),
+                _ => None,
+            };
+            if let Some((west_max, east_max)) = max {
+                if west < west_max || east < east_max {
+                    findings.push(finding(Severity::Info,
+                                          Some(name),
+                                          format!("{}/{} west and {}/{} east ports used",
+                                                  west,
+                                                  west_max,
+                                                  east,
+                                                  east_max)));
+                }
+            }
+        }
+        findings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::init;
+    use super::super::super::Side;
+    use nop::Nop;
+    use tap::Tap;
+    use switch::Switch;
+
+    #[test]
+    fn validate() {
+        init();
+        let mut g = Graph::new("graph");
+        assert_eq!(g.validate()[0].severity, Severity::Error);
+
+        g.add(Brick::Tap(Tap::new("tap")))
+            .unwrap()
+            .add(Brick::Switch(Switch::new("sw", 1, 2, Side::West)))
+            .unwrap()
+            .add(Brick::Nop(Nop::new("nop1")))
+            .unwrap()
+            .add(Brick::Nop(Nop::new("nop2")))
+            .unwrap();
+        g.link("tap", "sw").unwrap();
+        g.link("sw", "nop1").unwrap();
+        let findings = g.validate();
+        assert_eq!(findings.iter().map(|f| format!("{}", f)).collect::<Vec<String>>(),
+                   vec!["warning: nop1: nothing linked on east side",
+                        "warning: nop2: not linked",
+                        "info: sw: 1/1 west and 1/2 east ports used"]);
+
+        // Loop between nops
+        g.link("nop1", "nop2").unwrap();
+        g.link("nop2", "nop1").unwrap();
+        assert!(g.validate().iter().any(|f| f.message == "cycle through nop2 -> nop1"));
+        g.unlink("nop2", "nop1").unwrap();
+
+        let mut outside = Brick::Nop(Nop::new("outside"));
+        g.bricks.get_mut("nop2").unwrap().link(&mut outside).unwrap();
+        assert_eq!(g.edges().len(), 3);
+        let errors = g.validate()
+            .into_iter()
+            .filter(|f| f.severity == Severity::Error)
+            .collect::<Vec<Finding>>();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].brick, Some(String::from("nop2")));
+
+        let nop1 = g.bricks.remove("nop1").unwrap();
+        let errors = g.validate()
+            .into_iter()
+            .filter(|f| f.severity == Severity::Error && f.brick.is_none())
+            .map(|f| f.message)
+            .collect::<Vec<String>>();
+        assert_eq!(errors,
+                   vec!["link sw -> nop1: nop1 is not in the graph",
+                        "link nop1 -> nop2: nop1 is not in the graph"]);
+        drop(nop1);
+    }
+}
@@ -22,6 +22,8 @@ use packetgraph_sys::{pg_brick, pg_brick_destroy, pg_hub_new};
 pub struct Hub {
     pub brick: *mut pg_brick,
     pub name: String,
+    west_max: u32,
+    east_max: u32,
 }
 
 impl Hub {
@@ -33,6 +35,8 @@ impl Hub {
             Hub {
                 brick: pg_hub_new(cname.as_ptr(), west_max, east_max, &mut error.ptr),
                 name: name,
+                west_max: west_max,
+                east_max: east_max,
             }
         };
         assert!(!error.is_set());
@@ -42,6 +46,14 @@ impl Hub {
     pub fn pollable(&self) -> bool {
         false
     }
+
+    pub fn west_max(&self) -> u32 {
+        self.west_max
+    }
+
+    pub fn east_max(&self) -> u32 {
+        self.east_max
+    }
 }
 
 impl Drop for Hub {
@@ -19,6 +19,13 @@ extern crate packetgraph_sys;
 #[macro_use]
 extern crate lazy_static;
 extern crate libc;
+#[macro_use]
+extern crate bitflags;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate toml;
+extern crate serde_json;
 
 pub mod error;
 pub mod brick;
@@ -30,17 +37,25 @@ pub mod switch;
 pub mod nic;
 pub mod hub;
 pub mod vhost;
+pub mod queue;
+pub mod runner;
+pub mod scheduler;
+mod netlink;
+mod dpdk;
 
 pub use error::Error;
 pub use brick::Brick;
-pub use graph::Graph;
+pub use graph::{Graph, SendGraph, AddError};
 pub use nop::Nop;
 pub use firewall::Firewall;
 pub use tap::Tap;
 pub use switch::Switch;
 pub use nic::Nic;
 pub use hub::Hub;
-pub use vhost::Vhost;
+pub use vhost::{Vhost, VhostFlags, VhostConfig, QemuOptions, qemu_memory_args};
+pub use queue::Queue;
+pub use runner::{Runner, StopHandle, IdleStrategy};
+pub use scheduler::Scheduler;
 
 use std::env;
 use std::ffi::CString;
@@ -78,6 +93,8 @@ pub fn init() {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Side {
     West,
     East,
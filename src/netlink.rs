@@ -0,0 +1,202 @@
+/* Copyright 2017 Outscale SAS
+ *
+ * This file is part of Pg, a Rust Wrapper for packetgraph C library.
+ *
+ * Pg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 3 as published
+ * by the Free Software Foundation.
+ *
+ * Packetgraph is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Packetgraph.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// Minimal rtnetlink client used to configure host side interfaces (tap).
+// Each call opens a NETLINK_ROUTE socket, sends one request and waits for
+// the kernel acknowledgement.
+
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::net::IpAddr;
+use libc;
+
+const NLMSG_HDRLEN: usize = 16;
+const IFINFOMSG_LEN: usize = 16;
+const IFADDRMSG_LEN: usize = 8;
+const NLMSG_ERROR: u16 = 2;
+const RTM_NEWLINK: u16 = 16;
+const RTM_NEWADDR: u16 = 20;
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_ACK: u16 = 0x4;
+const NLM_F_EXCL: u16 = 0x200;
+const NLM_F_CREATE: u16 = 0x400;
+const IFLA_ADDRESS: u16 = 1;
+const IFLA_MTU: u16 = 4;
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+
+struct Request {
+    buf: Vec<u8>,
+}
+
+impl Request {
+    fn new(msg_type: u16, flags: u16) -> Request {
+        let mut buf = vec![0; NLMSG_HDRLEN];
+        buf[4..6].copy_from_slice(&u16_bytes(msg_type));
+        buf[6..8].copy_from_slice(&u16_bytes(flags | NLM_F_REQUEST | NLM_F_ACK));
+        buf[8..12].copy_from_slice(&u32_bytes(1));
+        Request { buf: buf }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+        while self.buf.len() % 4 != 0 {
+            self.buf.push(0);
+        }
+    }
+
+    fn attr(&mut self, kind: u16, data: &[u8]) {
+        let mut a = Vec::with_capacity(4 + data.len());
+        a.extend_from_slice(&u16_bytes(4 + data.len() as u16));
+        a.extend_from_slice(&u16_bytes(kind));
+        a.extend_from_slice(data);
+        self.push(&a);
+    }
+
+    fn send(mut self) -> io::Result<()> {
+        let len = self.buf.len() as u32;
+        self.buf[0..4].copy_from_slice(&u32_bytes(len));
+        unsafe {
+            let fd = libc::socket(libc::AF_NETLINK,
+                                  libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                                  libc::NETLINK_ROUTE);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let ret = self.exchange(fd);
+            libc::close(fd);
+            ret
+        }
+    }
+
+    unsafe fn exchange(&self, fd: libc::c_int) -> io::Result<()> {
+        let mut kernel: libc::sockaddr_nl = mem::zeroed();
+        kernel.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        if libc::sendto(fd,
+                        self.buf.as_ptr() as *const libc::c_void,
+                        self.buf.len(),
+                        0,
+                        &kernel as *const libc::sockaddr_nl as *const libc::sockaddr,
+                        mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut answer = [0u8; 4096];
+        loop {
+            let n = libc::recv(fd, answer.as_mut_ptr() as *mut libc::c_void, answer.len(), 0);
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let n = n as usize;
+            let mut off = 0;
+            while off + NLMSG_HDRLEN <= n {
+                let msg_len = read_u32(&answer[off..]) as usize;
+                let msg_type = read_u16(&answer[off + 4..]);
+                if msg_type == NLMSG_ERROR && off + NLMSG_HDRLEN + 4 <= n {
+                    let errno = read_u32(&answer[off + NLMSG_HDRLEN..]) as i32;
+                    return match errno {
+                        0 => Ok(()),
+                        e => Err(io::Error::from_raw_os_error(-e)),
+                    };
+                }
+                if msg_len < NLMSG_HDRLEN {
+                    break;
+                }
+                off += (msg_len + 3) & !3;
+            }
+        }
+    }
+}
+
+fn u16_bytes(v: u16) -> [u8; 2] {
+    v.to_ne_bytes()
+}
+
+fn u32_bytes(v: u32) -> [u8; 4] {
+    v.to_ne_bytes()
+}
+
+fn read_u16(b: &[u8]) -> u16 {
+    u16::from_ne_bytes([b[0], b[1]])
+}
+
+fn read_u32(b: &[u8]) -> u32 {
+    u32::from_ne_bytes([b[0], b[1], b[2], b[3]])
+}
+
+fn index(ifname: &str) -> io::Result<u32> {
+    let cifname = match CString::new(ifname) {
+        Ok(s) => s,
+        Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid ifname")),
+    };
+    match unsafe { libc::if_nametoindex(cifname.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        i => Ok(i),
+    }
+}
+
+fn ifinfomsg(index: u32, flags: u32, change: u32) -> [u8; IFINFOMSG_LEN] {
+    let mut m = [0u8; IFINFOMSG_LEN];
+    m[0] = libc::AF_UNSPEC as u8;
+    m[4..8].copy_from_slice(&u32_bytes(index));
+    m[8..12].copy_from_slice(&u32_bytes(flags));
+    m[12..16].copy_from_slice(&u32_bytes(change));
+    m
+}
+
+pub fn set_link_up(ifname: &str, up: bool) -> io::Result<()> {
+    let flags = match up {
+        true => libc::IFF_UP as u32,
+        false => 0,
+    };
+    let mut req = Request::new(RTM_NEWLINK, 0);
+    req.push(&ifinfomsg(index(ifname)?, flags, libc::IFF_UP as u32));
+    req.send()
+}
+
+pub fn set_mtu(ifname: &str, mtu: u32) -> io::Result<()> {
+    let mut req = Request::new(RTM_NEWLINK, 0);
+    req.push(&ifinfomsg(index(ifname)?, 0, 0));
+    req.attr(IFLA_MTU, &u32_bytes(mtu));
+    req.send()
+}
+
+pub fn set_mac(ifname: &str, mac: [u8; 6]) -> io::Result<()> {
+    let mut req = Request::new(RTM_NEWLINK, 0);
+    req.push(&ifinfomsg(index(ifname)?, 0, 0));
+    req.attr(IFLA_ADDRESS, &mac);
+    req.send()
+}
+
+pub fn add_address(ifname: &str, addr: IpAddr, prefix: u8) -> io::Result<()> {
+    let (family, max, bytes) = match addr {
+        IpAddr::V4(a) => (libc::AF_INET, 32, a.octets().to_vec()),
+        IpAddr::V6(a) => (libc::AF_INET6, 128, a.octets().to_vec()),
+    };
+    if prefix > max {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid prefix length"));
+    }
+    let mut msg = [0u8; IFADDRMSG_LEN];
+    msg[0] = family as u8;
+    msg[1] = prefix;
+    msg[4..8].copy_from_slice(&u32_bytes(index(ifname)?));
+    let mut req = Request::new(RTM_NEWADDR, NLM_F_CREATE | NLM_F_EXCL);
+    req.push(&msg);
+    req.attr(IFA_LOCAL, &bytes);
+    req.attr(IFA_ADDRESS, &bytes);
+    req.send()
+}
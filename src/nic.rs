@@ -16,12 +16,58 @@
  */
 
 use error::Error;
+use dpdk;
 use std::ffi::CString;
-use packetgraph_sys::{pg_brick, pg_brick_destroy, pg_nic_new, pg_nic_new_by_id};
+use std::mem;
+use std::path::Path;
+use std::sync::Mutex;
+use packetgraph_sys::{pg_brick, pg_brick_destroy, pg_nic_new, pg_nic_new_by_id, pg_nic_get_stats,
+                      pg_nic_get_mac, pg_nic_stats, ether_addr};
+
+lazy_static! {
+    // net_pcap vdev names must be unique
+    static ref PCAP_COUNT: Mutex<u32> = Mutex::new(0);
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NicStats {
+    pub ipackets: u64,
+    pub opackets: u64,
+    pub ibytes: u64,
+    pub obytes: u64,
+    pub ierrors: u64,
+    pub oerrors: u64,
+    pub imissed: u64,
+    pub rx_nombuf: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkStatus {
+    pub up: bool,
+    // Mbps
+    pub speed: u32,
+    pub full_duplex: bool,
+    pub autoneg: bool,
+}
+
+// DPDK port as listed by ports()
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortInfo {
+    pub id: u16,
+    // PCI address like "0000:03:00.0" or vdev name like "net_pcap0"
+    pub name: String,
+    // PMD name like "net_ixgbe", empty if DPDK doesn't report one
+    pub driver: String,
+    pub mac: [u8; 6],
+    // NUMA socket, None if unknown
+    pub socket: Option<u32>,
+}
 
 pub struct Nic {
     pub brick: *mut pg_brick,
     pub name: String,
+    vdev: Option<String>,
+    port: Option<u16>,
 }
 
 impl Nic {
@@ -30,27 +76,99 @@ impl Nic {
         let vdev = vdev.into();
         let cname = CString::new(name.as_str()).unwrap();
         let cvdev = CString::new(vdev.as_str()).unwrap();
+        // DPDK names the port after the device, without its arguments
+        let device = String::from(vdev.split(',').next().unwrap());
         let mut error = Error::new();
-        let b = unsafe {
+        let mut b = unsafe {
             Nic {
                 brick: pg_nic_new(cname.as_ptr(), cvdev.as_ptr(), &mut error.ptr),
                 name: name,
+                vdev: Some(vdev),
+                port: None,
             }
         };
         match error.is_set() {
             true => Err(error),
-            false => Ok(b),
+            false => {
+                b.port = dpdk::port_by_name(&device);
+                Ok(b)
+            }
+        }
+    }
+
+    // addr: PCI address like "0000:03:00.0" or "03:00.0" of a port probed by
+    // DPDK, see ports()
+    pub fn from_pci<S: Into<String>>(name: S, addr: &str) -> Result<Nic, Error> {
+        let mut error = Error::new();
+        if !valid_pci_addr(addr) {
+            error.set(format!("invalid PCI address {}", addr));
+            return Err(error);
+        }
+        let addr = match addr.matches(':').count() {
+            1 => format!("0000:{}", addr.to_lowercase()),
+            _ => addr.to_lowercase(),
+        };
+        match ports().into_iter().find(|p| p.name == addr) {
+            Some(p) => Nic::new_port(name, p.id),
+            None => {
+                error.set(format!("no DPDK port at PCI address {}, is the device bound to a \
+                                   DPDK driver ?",
+                                  addr));
+                Err(error)
+            }
+        }
+    }
+
+    // Build the vdev string from its driver name and arguments, for example
+    // ("net_pcap0", [("iface", "eth0")]) gives "net_pcap0,iface=eth0"
+    pub fn from_vdev_args<S: Into<String>>(name: S,
+                                           driver: &str,
+                                           args: &[(&str, &str)])
+                                           -> Result<Nic, Error> {
+        let mut vdev = String::from(driver);
+        for &(key, value) in args {
+            vdev.push_str(&format!(",{}={}", key, value));
         }
+        Nic::new(name.into(), vdev)
+    }
+
+    // Read packets from rx_pcap and write sent packets to tx_pcap using
+    // DPDK's net_pcap driver. Once rx_pcap is exhausted the nic stops
+    // receiving, see Graph::poll_until_idle().
+    pub fn pcap<S: Into<String>>(name: S, rx_pcap: &Path, tx_pcap: &Path) -> Result<Nic, Error> {
+        let mut error = Error::new();
+        let rx = match rx_pcap.to_str() {
+            Some(p) if !p.contains(',') => p,
+            _ => {
+                error.set(format!("unsupported pcap path {}", rx_pcap.display()));
+                return Err(error);
+            }
+        };
+        let tx = match tx_pcap.to_str() {
+            Some(p) if !p.contains(',') => p,
+            _ => {
+                error.set(format!("unsupported pcap path {}", tx_pcap.display()));
+                return Err(error);
+            }
+        };
+        let driver = {
+            let mut n = PCAP_COUNT.lock().unwrap();
+            *n += 1;
+            format!("net_pcap{}", *n - 1)
+        };
+        Nic::from_vdev_args(name, driver.as_str(), &[("rx_pcap", rx), ("tx_pcap", tx)])
     }
 
-    pub fn new_port<S: Into<String>>(name: S, port: u8) -> Result<Nic, Error> {
+    pub fn new_port<S: Into<String>>(name: S, port: u16) -> Result<Nic, Error> {
         let name = name.into();
         let cname = CString::new(name.as_str()).unwrap();
         let mut error = Error::new();
         let b = unsafe {
             Nic {
-                brick: pg_nic_new_by_id(cname.as_ptr(), port as u16, &mut error.ptr),
+                brick: pg_nic_new_by_id(cname.as_ptr(), port, &mut error.ptr),
                 name: name,
+                vdev: None,
+                port: Some(port),
             }
         };
         match error.is_set() {
@@ -62,6 +180,92 @@ impl Nic {
     pub fn pollable(&self) -> bool {
         true
     }
+
+    // DPDK port behind the nic, None if its device could not be found among
+    // DPDK ports when the nic was created
+    pub fn port_id(&self) -> Option<u16> {
+        self.port
+    }
+
+    // Device string given at creation, None when created with new_port()
+    pub fn vdev(&self) -> Option<&str> {
+        self.vdev.as_deref()
+    }
+
+    pub fn stats(&self) -> NicStats {
+        let mut s: pg_nic_stats = unsafe { mem::zeroed() };
+        unsafe {
+            pg_nic_get_stats(self.brick, &mut s);
+        }
+        NicStats {
+            ipackets: s.ipackets,
+            opackets: s.opackets,
+            ibytes: s.ibytes,
+            obytes: s.obytes,
+            ierrors: s.ierrors,
+            oerrors: s.oerrors,
+            imissed: s.imissed,
+            rx_nombuf: s.rx_nombuf,
+        }
+    }
+
+    pub fn mac(&self) -> [u8; 6] {
+        let mut addr: ether_addr = unsafe { mem::zeroed() };
+        unsafe {
+            pg_nic_get_mac(self.brick, &mut addr);
+        }
+        addr.addr_bytes
+    }
+
+    pub fn link_status(&self) -> Result<LinkStatus, Error> {
+        let port = match self.port {
+            Some(p) => p,
+            None => {
+                let mut error = Error::new();
+                error.set(format!("no DPDK port known for nic {}", self.name));
+                return Err(error);
+            }
+        };
+        let l = dpdk::link(port);
+        Ok(LinkStatus {
+            up: l.up,
+            speed: l.speed,
+            full_duplex: l.full_duplex,
+            autoneg: l.autoneg,
+        })
+    }
+}
+
+pub fn ports() -> Vec<PortInfo> {
+    dpdk::ports()
+        .into_iter()
+        .map(|id| {
+            PortInfo {
+                id: id,
+                name: dpdk::name(id).unwrap_or_default(),
+                driver: dpdk::driver(id).unwrap_or_default(),
+                mac: dpdk::mac(id),
+                socket: dpdk::socket(id),
+            }
+        })
+        .collect()
+}
+
+fn valid_pci_addr(addr: &str) -> bool {
+    let hex = |s: &str, len: usize| s.len() == len && s.chars().all(|c| c.is_ascii_hexdigit());
+    let (bus, function) = match addr.rfind('.') {
+        Some(i) => (&addr[..i], &addr[i + 1..]),
+        None => return false,
+    };
+    if function.len() != 1 || !function.chars().all(|c| c.is_digit(8)) {
+        return false;
+    }
+    let parts: Vec<&str> = bus.split(':').collect();
+    match parts.len() {
+        2 => hex(parts[0], 2) && hex(parts[1], 2),
+        3 => hex(parts[0], 4) && hex(parts[1], 2) && hex(parts[2], 2),
+        _ => false,
+    }
 }
 
 impl Drop for Nic {
@@ -76,7 +280,67 @@ impl Drop for Nic {
 mod tests {
     use super::*;
     use super::super::init;
+    use super::super::Side;
     use super::super::brick::Brick;
+    use super::super::firewall::Firewall;
+    use super::super::graph::Graph;
+    use std::env;
+    use std::fs::File;
+    use std::io::prelude::*;
+    use std::path::PathBuf;
+
+    // Ethernet + IPv4 + UDP frame from src to dst
+    fn udp_frame(src: [u8; 4], dst: [u8; 4]) -> Vec<u8> {
+        let mut f = vec![0x52, 0x54, 0x00, 0x00, 0x00, 0x02, 0x52, 0x54, 0x00, 0x00, 0x00, 0x01,
+                         0x08, 0x00];
+        let mut ip = vec![0x45, 0x00, 0x00, 0x24, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00];
+        ip.extend_from_slice(&src);
+        ip.extend_from_slice(&dst);
+        let mut sum: u32 = 0;
+        for w in ip.chunks(2) {
+            sum += ((w[0] as u32) << 8) | w[1] as u32;
+        }
+        while sum > 0xffff {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        ip[10] = (!sum >> 8) as u8;
+        ip[11] = !sum as u8;
+        f.extend(ip);
+        f.extend_from_slice(&[0x04, 0xd2, 0x16, 0x2e, 0x00, 0x10, 0x00, 0x00]);
+        f.extend_from_slice(b"pgtestpg");
+        f
+    }
+
+    fn write_pcap(path: &PathBuf, frames: &[Vec<u8>]) {
+        let mut out: Vec<u8> = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                                    0xff, 0xff, 0, 0, 1, 0, 0, 0];
+        for f in frames {
+            let len = f.len() as u32;
+            out.extend_from_slice(&[0; 8]);
+            for _ in 0..2 {
+                out.extend_from_slice(&[len as u8, (len >> 8) as u8, (len >> 16) as u8,
+                                        (len >> 24) as u8]);
+            }
+            out.extend_from_slice(f);
+        }
+        File::create(path).unwrap().write_all(&out).unwrap();
+    }
+
+    fn read_pcap(path: &PathBuf) -> Vec<Vec<u8>> {
+        let mut data = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut data).unwrap();
+        let le32 = |b: &[u8]| {
+            b[0] as usize | (b[1] as usize) << 8 | (b[2] as usize) << 16 | (b[3] as usize) << 24
+        };
+        let mut frames = Vec::new();
+        let mut i = 24;
+        while i + 16 <= data.len() {
+            let len = le32(&data[i + 8..]);
+            frames.push(data[i + 16..i + 16 + len].to_vec());
+            i += 16 + len;
+        }
+        frames
+    }
 
     #[test]
     fn rings() {
@@ -89,4 +353,61 @@ mod tests {
         b1.poll().unwrap();
         b2.poll().unwrap();
     }
+
+    #[test]
+    fn stats_mac() {
+        init();
+        let nic = Nic::new("nic", "eth_ring2").unwrap();
+        let stats = nic.stats();
+        assert_eq!(stats.opackets, 0);
+        assert_eq!(stats.obytes, 0);
+        assert!(nic.port_id().is_some());
+        // eth_ring links are up at 10G full duplex once started
+        let link = nic.link_status().unwrap();
+        assert!(link.up);
+        assert_eq!(link.speed, 10000);
+        assert!(link.full_duplex);
+    }
+
+    #[test]
+    fn ports_vdev_pci() {
+        init();
+        let nic = Nic::from_vdev_args("nic", "eth_ring3", &[]).unwrap();
+        let port = ports().into_iter().find(|p| p.name == "eth_ring3").unwrap();
+        assert_eq!(Some(port.id), nic.port_id());
+        assert_eq!(port.mac, nic.mac());
+        assert_eq!(port.driver, "net_ring");
+        assert!(Nic::from_pci("nic", "not a pci address").is_err());
+        assert!(Nic::from_pci("nic", "0000:03:00.9").is_err());
+        assert!(valid_pci_addr("0000:03:00.0"));
+        assert!(valid_pci_addr("03:1f.7"));
+    }
+
+    #[test]
+    fn pcap_firewall() {
+        init();
+        let dir = env::temp_dir();
+        let input = dir.join("pg_pcap_firewall_in.pcap");
+        let empty = dir.join("pg_pcap_firewall_empty.pcap");
+        let discard = dir.join("pg_pcap_firewall_discard.pcap");
+        let output = dir.join("pg_pcap_firewall_out.pcap");
+        let allowed = udp_frame([10, 0, 0, 1], [10, 0, 0, 2]);
+        let denied = udp_frame([10, 0, 0, 3], [10, 0, 0, 2]);
+        write_pcap(&input, &[allowed.clone(), denied.clone(), allowed.clone()]);
+        write_pcap(&empty, &[]);
+
+        let mut nic_in = Brick::Nic(Nic::pcap("in", &input, &discard).unwrap());
+        let mut fw = Brick::Firewall(Firewall::new("fw"));
+        let mut nic_out = Brick::Nic(Nic::pcap("out", &empty, &output).unwrap());
+        fw.firewall().unwrap().rule_add("src host 10.0.0.1", Side::West).unwrap();
+        fw.firewall().unwrap().reload().unwrap();
+        nic_in.link(&mut fw).unwrap();
+        fw.link(&mut nic_out).unwrap();
+        let mut g = Graph::new("pcap");
+        g.add(nic_in).unwrap().add(fw).unwrap().add(nic_out).unwrap();
+        assert_eq!(g.poll_until_idle(10).unwrap(), 3);
+        // Flush tx_pcap
+        drop(g);
+        assert_eq!(read_pcap(&output), vec![allowed.clone(), allowed]);
+    }
 }
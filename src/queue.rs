@@ -0,0 +1,119 @@
+/* Copyright 2017 Outscale SAS
+ *
+ * This file is part of Pg, a Rust Wrapper for packetgraph C library.
+ *
+ * Pg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 3 as published
+ * by the Free Software Foundation.
+ *
+ * Packetgraph is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Packetgraph.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// Queues carry packets between two graphs polled by different threads: a
+// queue is linked on one side only and, once friend with another queue,
+// packets it receives are polled out of its friend.
+
+use error::Error;
+use std::ffi::CString;
+use libc::c_int;
+use packetgraph_sys::{pg_brick, pg_brick_destroy, pg_queue_new, pg_queue_friend,
+                      pg_queue_unfriend};
+
+pub struct Queue {
+    pub brick: *mut pg_brick,
+    pub name: String,
+    size: u32,
+}
+
+impl Queue {
+    // `size` is the number of packets the queue holds, 0 for packetgraph's
+    // default
+    pub fn new<S: Into<String>>(name: S, size: u32) -> Result<Queue, Error> {
+        let name = name.into();
+        let cname = CString::new(name.as_str()).unwrap();
+        let mut error = Error::new();
+        let q = unsafe {
+            Queue {
+                brick: pg_queue_new(cname.as_ptr(), size as c_int, &mut error.ptr),
+                name: name,
+                size: size,
+            }
+        };
+        match error.is_set() {
+            true => Err(error),
+            false => Ok(q),
+        }
+    }
+
+    pub fn pollable(&self) -> bool {
+        true
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    // Each queue can only have one friend
+    pub fn friend(&mut self, other: &mut Queue) -> Result<(), Error> {
+        let mut error = Error::new();
+        unsafe {
+            pg_queue_friend(self.brick, other.brick, &mut error.ptr);
+        }
+        match error.is_set() {
+            true => Err(error),
+            false => Ok(()),
+        }
+    }
+
+    // Unfriend both queues
+    pub fn unfriend(&mut self) {
+        unsafe {
+            pg_queue_unfriend(self.brick);
+        }
+    }
+}
+
+impl Drop for Queue {
+    fn drop(&mut self) {
+        unsafe {
+            pg_queue_unfriend(self.brick);
+            pg_brick_destroy(self.brick);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::brick::Brick;
+    use super::super::nop::Nop;
+    use super::super::init;
+
+    #[test]
+    fn friend() {
+        init();
+        let mut q1 = Queue::new("q1", 0).unwrap();
+        let mut q2 = Queue::new("q2", 64).unwrap();
+        let mut q3 = Queue::new("q3", 0).unwrap();
+        assert_eq!(q2.size(), 64);
+        q1.friend(&mut q2).unwrap();
+        assert!(q3.friend(&mut q1).is_err());
+        q2.unfriend();
+        q3.friend(&mut q1).unwrap();
+
+        let mut nop = Brick::Nop(Nop::new("nop"));
+        let mut b1 = Brick::Queue(q1);
+        let mut b3 = Brick::Queue(q3);
+        nop.link(&mut b1).unwrap();
+        b3.link(&mut nop).unwrap();
+        assert!(b1.pollable());
+        b1.poll().unwrap();
+        b3.poll().unwrap();
+    }
+}
@@ -0,0 +1,278 @@
+/* Copyright 2017 Outscale SAS
+ *
+ * This file is part of Pg, a Rust Wrapper for packetgraph C library.
+ *
+ * Pg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 3 as published
+ * by the Free Software Foundation.
+ *
+ * Packetgraph is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Packetgraph.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use error::Error;
+use graph::Graph;
+
+// What to do when a poll round got no packet
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IdleStrategy {
+    // Poll again right away, lowest latency but takes a whole CPU
+    Busy,
+    Yield,
+    // Sleep `min`, doubled at each idle round up to `max`
+    Backoff { min: Duration, max: Duration },
+}
+
+impl Default for IdleStrategy {
+    fn default() -> IdleStrategy {
+        IdleStrategy::Backoff {
+            min: Duration::from_micros(10),
+            max: Duration::from_millis(1),
+        }
+    }
+}
+
+// Stop a Runner or a Scheduler from another thread
+#[derive(Clone, Default)]
+pub struct StopHandle {
+    stop: Arc<AtomicBool>,
+}
+
+impl StopHandle {
+    pub fn new() -> StopHandle {
+        StopHandle::default()
+    }
+
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+
+    // Forget a stop request
+    pub fn reset(&self) {
+        self.stop.store(false, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RunStats {
+    // Poll rounds over all pollable bricks
+    pub rounds: u64,
+    // Rounds which got packets
+    pub busy_rounds: u64,
+    pub packets: u64,
+    pub errors: u64,
+}
+
+// Called with brick name, poll error and how many errors of this brick were
+// not reported since the last call
+type ErrorCallback = Box<dyn FnMut(&str, &Error, u64) + Send>;
+
+struct ErrorReport {
+    last: Option<Instant>,
+    dropped: u64,
+}
+
+pub struct Runner {
+    graph: Graph,
+    idle: IdleStrategy,
+    stop: StopHandle,
+    on_error: Option<ErrorCallback>,
+    error_interval: Duration,
+    reports: HashMap<String, ErrorReport>,
+    stats: RunStats,
+}
+
+impl Runner {
+    pub fn new(graph: Graph) -> Runner {
+        Runner {
+            graph: graph,
+            idle: IdleStrategy::default(),
+            stop: StopHandle::new(),
+            on_error: None,
+            error_interval: Duration::from_secs(1),
+            reports: HashMap::new(),
+            stats: RunStats::default(),
+        }
+    }
+
+    pub fn idle(mut self, idle: IdleStrategy) -> Runner {
+        self.idle = idle;
+        self
+    }
+
+    // Report poll errors, at most once per `interval` for each brick
+    pub fn on_error<F>(mut self, interval: Duration, callback: F) -> Runner
+        where F: FnMut(&str, &Error, u64) + Send + 'static
+    {
+        self.on_error = Some(Box::new(callback));
+        self.error_interval = interval;
+        self
+    }
+
+    // Stop on `stop` instead of a handle of its own
+    pub fn stop_on(mut self, stop: StopHandle) -> Runner {
+        self.stop = stop;
+        self
+    }
+
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop.clone()
+    }
+
+    pub fn graph(&mut self) -> &mut Graph {
+        &mut self.graph
+    }
+
+    pub fn into_graph(self) -> Graph {
+        self.graph
+    }
+
+    pub fn stats(&self) -> RunStats {
+        self.stats
+    }
+
+    // Poll all pollable bricks once, returns the number of polled packets
+    pub fn poll_once(&mut self) -> usize {
+        let mut packets = 0;
+        let mut errors = Vec::new();
+        for (name, b) in self.graph.bricks.iter_mut().filter(|b| b.1.pollable()) {
+            match b.poll() {
+                Ok(n) => packets += n,
+                Err(e) => errors.push((name.clone(), e)),
+            }
+        }
+        self.stats.rounds += 1;
+        if packets > 0 {
+            self.stats.busy_rounds += 1;
+        }
+        self.stats.packets += packets as u64;
+        for (name, e) in errors {
+            self.report(name, e);
+        }
+        packets
+    }
+
+    // Poll until stopped, the stop flag is cleared so it can be run again
+    pub fn run(&mut self) -> RunStats {
+        let mut sleep = None;
+        while !self.stop.is_stopped() {
+            if self.poll_once() > 0 {
+                sleep = None;
+                continue;
+            }
+            match self.idle {
+                IdleStrategy::Busy => {}
+                IdleStrategy::Yield => thread::yield_now(),
+                IdleStrategy::Backoff { min, max } => {
+                    let d = match sleep {
+                        None => min,
+                        Some(d) if d * 2 > max => max,
+                        Some(d) => d * 2,
+                    };
+                    thread::sleep(d);
+                    sleep = Some(d);
+                }
+            }
+        }
+        self.stop.reset();
+        self.stats
+    }
+
+    fn report(&mut self, name: String, e: Error) {
+        self.stats.errors += 1;
+        let callback = match self.on_error {
+            Some(ref mut c) => c,
+            None => return,
+        };
+        let now = Instant::now();
+        let r = self.reports.entry(name.clone()).or_insert(ErrorReport {
+            last: None,
+            dropped: 0,
+        });
+        if let Some(last) = r.last {
+            if now.duration_since(last) < self.error_interval {
+                r.dropped += 1;
+                return;
+            }
+        }
+        callback(&name, &e, r.dropped);
+        r.last = Some(now);
+        r.dropped = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use super::super::init;
+    use brick::Brick;
+    use nop::Nop;
+    use tap::Tap;
+
+    #[test]
+    fn run_stop() {
+        init();
+        let mut g = Graph::new("graph");
+        g.add(Brick::Tap(Tap::new("tap1")))
+            .unwrap()
+            .add(Brick::Nop(Nop::new("nop")))
+            .unwrap()
+            .add(Brick::Tap(Tap::new("tap2")))
+            .unwrap();
+        g.link("tap1", "nop").unwrap();
+        g.link("nop", "tap2").unwrap();
+
+        let g = g.into_send().ok().unwrap();
+        let stop = StopHandle::new();
+        let worker_stop = stop.clone();
+        let (tx, rx) = channel();
+        let t = thread::spawn(move || {
+            let mut runner = Runner::new(g.into_graph())
+                .idle(IdleStrategy::Yield)
+                .stop_on(worker_stop);
+            let stats = runner.run();
+            tx.send(stats).unwrap();
+            runner.into_graph().into_send().ok().unwrap()
+        });
+        thread::sleep(Duration::from_millis(50));
+        stop.stop();
+        let stats = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(stats.rounds > 0);
+        assert_eq!(stats.errors, 0);
+        let g = t.join().unwrap().into_graph();
+        assert!(!stop.is_stopped());
+        assert_eq!(g.bricks.len(), 3);
+    }
+
+    #[test]
+    fn backoff() {
+        init();
+        let mut runner = Runner::new(Graph::new("graph")).idle(IdleStrategy::Backoff {
+            min: Duration::from_millis(1),
+            max: Duration::from_millis(4),
+        });
+        let stop = runner.stop_handle();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            stop.stop();
+        });
+        let stats = runner.run();
+        // Sleeps reach 4ms quickly, a busy loop would do way more rounds
+        assert!(stats.rounds > 0 && stats.rounds < 100);
+    }
+}
@@ -0,0 +1,427 @@
+/* Copyright 2017 Outscale SAS
+ *
+ * This file is part of Pg, a Rust Wrapper for packetgraph C library.
+ *
+ * Pg is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License version 3 as published
+ * by the Free Software Foundation.
+ *
+ * Packetgraph is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Packetgraph.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+// Poll a graph from several threads. Polling a brick pushes packets through
+// every brick linked to it, so each worker polls a graph of its own, moved to
+// its thread as a SendGraph. Connected components are spread whole over
+// workers by number of pollable bricks. When there are fewer components than
+// workers, pollable bricks are spread one by one instead and other bricks
+// follow their nearest pollable brick: each link between two workers is then
+// replaced by a pair of friend queues for the run. Components without any
+// pollable brick are not polled and stay in the graph.
+
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+use libc;
+use error::Error;
+use brick::Brick;
+use graph::{Graph, Edge};
+use queue::Queue;
+use runner::{Runner, RunStats, IdleStrategy, StopHandle};
+use Side;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkerStats {
+    pub cpu: Option<usize>,
+    // Bricks polled by this worker, queues included
+    pub bricks: Vec<String>,
+    pub stats: RunStats,
+}
+
+impl WorkerStats {
+    // Share of poll rounds which got packets
+    pub fn load(&self) -> f64 {
+        match self.stats.rounds {
+            0 => 0.0,
+            n => self.stats.busy_rounds as f64 / n as f64,
+        }
+    }
+}
+
+pub struct Scheduler {
+    graph: Graph,
+    workers: usize,
+    cpus: Vec<usize>,
+    idle: IdleStrategy,
+    stop: StopHandle,
+}
+
+// Pin calling thread on `cpu`
+fn pin(cpu: usize) -> Result<(), String> {
+    if cpu >= libc::CPU_SETSIZE as usize {
+        return Err(format!("invalid cpu {}", cpu));
+    }
+    unsafe {
+        let mut set = mem::zeroed::<libc::cpu_set_t>();
+        libc::CPU_ZERO(&mut set);
+        libc::CPU_SET(cpu, &mut set);
+        if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) < 0 {
+            return Err(format!("cannot pin worker on cpu {}: {}",
+                               cpu,
+                               ::std::io::Error::last_os_error()));
+        }
+    }
+    Ok(())
+}
+
+impl Scheduler {
+    pub fn new(graph: Graph) -> Scheduler {
+        Scheduler {
+            graph: graph,
+            workers: 1,
+            cpus: Vec::new(),
+            idle: IdleStrategy::default(),
+            stop: StopHandle::new(),
+        }
+    }
+
+    pub fn workers(mut self, workers: usize) -> Scheduler {
+        self.workers = workers;
+        self
+    }
+
+    // Pin worker i on cpus[i % cpus.len()]. Without cpus, workers are not
+    // pinned.
+    pub fn cpus(mut self, cpus: Vec<usize>) -> Scheduler {
+        self.cpus = cpus;
+        self
+    }
+
+    pub fn idle(mut self, idle: IdleStrategy) -> Scheduler {
+        self.idle = idle;
+        self
+    }
+
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop.clone()
+    }
+
+    pub fn graph(&mut self) -> &mut Graph {
+        &mut self.graph
+    }
+
+    pub fn into_graph(self) -> Graph {
+        self.graph
+    }
+
+    // Brick names of each worker, empty workers are dropped
+    pub fn partition(&self) -> Vec<Vec<String>> {
+        let mut workers = vec![Vec::new(); self.workers.max(1)];
+        for (name, w) in self.assignment() {
+            workers[w].push(name);
+        }
+        workers.into_iter()
+            .map(|mut names| {
+                names.sort();
+                names
+            })
+            .filter(|names| !names.is_empty())
+            .collect()
+    }
+
+    // Worker of each polled brick
+    fn assignment(&self) -> HashMap<String, usize> {
+        let workers = self.workers.max(1);
+        let mut sorted = Vec::new();
+        for names in self.graph.connected_components() {
+            let pollable = names.iter().filter(|n| self.graph.bricks[*n].pollable()).count();
+            if pollable > 0 {
+                sorted.push((pollable, names));
+            }
+        }
+        // Biggest components first, ties sorted by name to stay deterministic
+        sorted.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+        let mut assignment = HashMap::new();
+        if sorted.len() >= workers {
+            let mut load = vec![0; workers];
+            for (pollable, names) in sorted {
+                let w = (0..workers).min_by_key(|w| load[*w]).unwrap();
+                load[w] += pollable;
+                for name in names {
+                    assignment.insert(name, w);
+                }
+            }
+            return assignment;
+        }
+
+        let mut queue = VecDeque::new();
+        for name in sorted.iter()
+            .flat_map(|c| c.1.iter())
+            .filter(|n| self.graph.bricks[*n].pollable()) {
+            assignment.insert(name.clone(), queue.len() % workers);
+            queue.push_back(name.clone());
+        }
+        // Breadth first from all pollable bricks, the nearest one wins
+        while let Some(name) = queue.pop_front() {
+            let w = assignment[&name];
+            for side in &[Side::West, Side::East] {
+                for n in self.graph.neighbours(&name, *side).unwrap() {
+                    if self.graph.bricks.contains_key(&n) && !assignment.contains_key(&n) {
+                        assignment.insert(n.clone(), w);
+                        queue.push_back(n);
+                    }
+                }
+            }
+        }
+        assignment
+    }
+
+    // Replace links between workers by friend queues, added queues and their
+    // worker are pushed to `queues`, even on error.
+    fn add_queues(&mut self,
+                  assignment: &HashMap<String, usize>,
+                  queues: &mut Vec<(String, usize)>)
+                  -> Result<(), Error> {
+        for e in self.graph.edges() {
+            let (w, east_w) = (assignment[&e.west], assignment[&e.east]);
+            if w == east_w {
+                continue;
+            }
+            let (west_q, east_q) = (format!("{}->{}:west", e.west, e.east),
+                                    format!("{}->{}:east", e.west, e.east));
+            for name in &[&west_q, &east_q] {
+                if self.graph.bricks.contains_key(*name) {
+                    let mut error = Error::new();
+                    error.set(format!("cannot add queue {}, name already used", name));
+                    return Err(error);
+                }
+            }
+            let mut west = Queue::new(west_q.as_str(), 0)?;
+            let mut east = Queue::new(east_q.as_str(), 0)?;
+            west.friend(&mut east)?;
+            self.graph.unlink(&e.west, &e.east)?;
+            self.graph.add(Brick::Queue(west))?;
+            queues.push((west_q.clone(), w));
+            self.graph.add(Brick::Queue(east))?;
+            queues.push((east_q.clone(), east_w));
+            self.graph.link(&e.west, &west_q)?;
+            self.graph.link(&east_q, &e.east)?;
+        }
+        Ok(())
+    }
+
+    // Remove queues and put back links as they were before the run
+    fn remove_queues(&mut self, queues: &[(String, usize)], edges: &[Edge]) {
+        if queues.is_empty() {
+            return;
+        }
+        for (name, _) in queues {
+            self.graph.remove(name);
+        }
+        self.graph.relink(edges);
+    }
+
+    // Poll until stopped. Bricks are moved to workers for the run and are
+    // back in the graph when it returns. The graph must be closed, see
+    // Graph::check_closed().
+    pub fn run(&mut self) -> Result<Vec<WorkerStats>, Error> {
+        self.graph.check_closed()?;
+        let assignment = self.assignment();
+        let edges = self.graph.edges();
+        let mut queues = Vec::new();
+        if let Err(e) = self.add_queues(&assignment, &mut queues) {
+            self.remove_queues(&queues, &edges);
+            return Err(e);
+        }
+        let result = self.run_workers(&assignment, &queues);
+        self.remove_queues(&queues, &edges);
+        result
+    }
+
+    fn run_workers(&mut self,
+                   assignment: &HashMap<String, usize>,
+                   queues: &[(String, usize)])
+                   -> Result<Vec<WorkerStats>, Error> {
+        let mut partition = vec![Vec::new(); self.workers.max(1)];
+        for (name, w) in assignment.iter().chain(queues.iter().map(|q| (&q.0, &q.1))) {
+            partition[*w].push(name.clone());
+        }
+        partition.retain(|names| !names.is_empty());
+        let mut graphs = Vec::new();
+        for (i, names) in partition.iter_mut().enumerate() {
+            names.sort();
+            let name = format!("{}-{}", self.graph.name, i);
+            let names = names.iter().map(|n| n.as_str()).collect::<Vec<&str>>();
+            // Queues took links between workers, nothing is cut
+            graphs.push(self.graph.split(name, &names)?.0);
+        }
+        // Links of a worker stay inside of it, so this only fails if the
+        // graph was not closed
+        let mut sendable = Vec::new();
+        for g in graphs {
+            match g.into_send() {
+                Ok(g) => sendable.push(g),
+                Err((mut g, e)) => {
+                    self.graph.merge(&mut g)?;
+                    for g in sendable {
+                        self.graph.merge(&mut g.into_graph())?;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut handles = Vec::new();
+        let (ready_tx, ready_rx) = channel();
+        for (i, (names, g)) in partition.into_iter().zip(sendable).enumerate() {
+            let cpu = match self.cpus.is_empty() {
+                true => None,
+                false => Some(self.cpus[i % self.cpus.len()]),
+            };
+            let stop = StopHandle::new();
+            let worker_stop = stop.clone();
+            let idle = self.idle;
+            let ready = ready_tx.clone();
+            let thread = thread::spawn(move || {
+                let mut runner = Runner::new(g.into_graph()).idle(idle).stop_on(worker_stop);
+                let pinned = cpu.map_or(Ok(()), pin);
+                let ok = pinned.is_ok();
+                ready.send(pinned).unwrap();
+                if ok {
+                    runner.run();
+                }
+                let stats = runner.stats();
+                // Error is not Send
+                (stats, runner.into_graph().into_send().map_err(|(_, e)| format!("{}", e)))
+            });
+            handles.push((cpu, names, stop, thread));
+        }
+
+        let mut error = Error::new();
+        for _ in 0..handles.len() {
+            if let Err(e) = ready_rx.recv().unwrap() {
+                error.set(e);
+            }
+        }
+        while !error.is_set() && !self.stop.is_stopped() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        self.stop.reset();
+
+        let mut stats = Vec::new();
+        for h in &handles {
+            h.2.stop();
+        }
+        for (cpu, names, _, thread) in handles {
+            let (worker, g) = thread.join().unwrap();
+            stats.push(WorkerStats {
+                cpu: cpu,
+                bricks: names,
+                stats: worker,
+            });
+            match g {
+                Ok(g) => self.graph.merge(&mut g.into_graph())?,
+                Err(e) => error.set(format!("worker graph lost: {}", e)),
+            }
+        }
+        match error.is_set() {
+            true => Err(error),
+            false => Ok(stats),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::init;
+    use brick::Brick;
+    use nop::Nop;
+    use tap::Tap;
+
+    fn component(g: &mut Graph, n: usize) {
+        let (west, nop, east) = (format!("tap{}w", n), format!("nop{}", n), format!("tap{}e", n));
+        g.add(Brick::Tap(Tap::new(west.as_str())))
+            .unwrap()
+            .add(Brick::Nop(Nop::new(nop.as_str())))
+            .unwrap()
+            .add(Brick::Tap(Tap::new(east.as_str())))
+            .unwrap();
+        g.link(&west, &nop).unwrap();
+        g.link(&nop, &east).unwrap();
+    }
+
+    #[test]
+    fn partition() {
+        init();
+        let mut g = Graph::new("graph");
+        component(&mut g, 1);
+        component(&mut g, 2);
+        // Not polled
+        g.add(Brick::Nop(Nop::new("alone"))).unwrap();
+        let s = Scheduler::new(g).workers(2);
+        assert_eq!(s.partition(),
+                   vec![vec!["nop1", "tap1e", "tap1w"], vec!["nop2", "tap2e", "tap2w"]]);
+        // More workers than components, they are split
+        let s = s.workers(3);
+        assert_eq!(s.partition(),
+                   vec![vec!["nop1", "tap1e", "tap2w"], vec!["tap1w"], vec!["nop2", "tap2e"]]);
+        let s = s.workers(1);
+        assert_eq!(s.partition()[0].len(), 6);
+    }
+
+    #[test]
+    fn run_stop() {
+        init();
+        let mut g = Graph::new("graph");
+        component(&mut g, 1);
+        component(&mut g, 2);
+        let mut s = Scheduler::new(g).workers(2).cpus(vec![0]).idle(IdleStrategy::Yield);
+        let stop = s.stop_handle();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            stop.stop();
+        });
+        let stats = s.run().unwrap();
+        assert_eq!(stats.len(), 2);
+        for w in &stats {
+            assert_eq!(w.cpu, Some(0));
+            assert_eq!(w.bricks.len(), 3);
+            assert!(w.stats.rounds > 0);
+        }
+        assert_eq!(s.graph().bricks.len(), 6);
+        assert_eq!(s.graph().edges().len(), 4);
+
+        let mut s = Scheduler::new(s.into_graph()).cpus(vec![100000]);
+        assert!(s.run().is_err());
+        assert_eq!(s.graph().bricks.len(), 6);
+    }
+
+    #[test]
+    fn run_queues() {
+        init();
+        let mut g = Graph::new("graph");
+        component(&mut g, 1);
+        let edges = g.edges();
+        let mut s = Scheduler::new(g).workers(2).idle(IdleStrategy::Yield);
+        let stop = s.stop_handle();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            stop.stop();
+        });
+        let stats = s.run().unwrap();
+        assert_eq!(stats[0].bricks, vec!["nop1", "tap1e", "tap1w->nop1:east"]);
+        assert_eq!(stats[1].bricks, vec!["tap1w", "tap1w->nop1:west"]);
+        assert!(stats.iter().all(|w| w.stats.rounds > 0));
+        assert_eq!(s.graph().bricks.len(), 3);
+        assert_eq!(s.graph().edges(), edges);
+    }
+}
@@ -23,6 +23,9 @@ use packetgraph_sys::{pg_brick, pg_brick_destroy, pg_switch_new};
 pub struct Switch {
     pub brick: *mut pg_brick,
     pub name: String,
+    west_max: u32,
+    east_max: u32,
+    output: Side,
 }
 
 impl Switch {
@@ -38,6 +41,9 @@ impl Switch {
                                      output.into(),
                                      &mut error.ptr),
                 name: name,
+                west_max: west_max,
+                east_max: east_max,
+                output: output,
             }
         };
         assert!(!error.is_set());
@@ -47,6 +53,18 @@ impl Switch {
     pub fn pollable(&self) -> bool {
         false
     }
+
+    pub fn west_max(&self) -> u32 {
+        self.west_max
+    }
+
+    pub fn east_max(&self) -> u32 {
+        self.east_max
+    }
+
+    pub fn output(&self) -> Side {
+        self.output
+    }
 }
 
 impl Drop for Switch {
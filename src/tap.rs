@@ -16,9 +16,14 @@
  */
 
 use error::Error;
-use std::ffi::CString;
+use std::ffi::{CString, CStr};
+use std::io;
+use std::mem;
+use std::net::IpAddr;
 use std::ptr;
-use packetgraph_sys::{pg_brick, pg_brick_destroy, pg_tap_new};
+use netlink;
+use packetgraph_sys::{pg_brick, pg_brick_destroy, pg_tap_new, pg_tap_ifname, pg_tap_get_mac,
+                      ether_addr};
 
 pub struct Tap {
     pub brick: *mut pg_brick,
@@ -38,9 +43,81 @@ impl Tap {
         }
     }
 
+    // Create a tap with a chosen interface name instead of letting the
+    // kernel pick one
+    pub fn with_ifname<S: Into<String>>(name: S, ifname: S) -> Result<Tap, Error> {
+        let name = name.into();
+        let cname = CString::new(name.as_str()).unwrap();
+        let cifname = CString::new(ifname.into()).unwrap();
+        let mut error = Error::new();
+        let b = unsafe {
+            Tap {
+                brick: pg_tap_new(cname.as_ptr(), cifname.as_ptr(), &mut error.ptr),
+                name: name,
+            }
+        };
+        match error.is_set() {
+            true => Err(error),
+            false => Ok(b),
+        }
+    }
+
     pub fn pollable(&self) -> bool {
         true
     }
+
+    // Host side interface name
+    pub fn ifname(&self) -> String {
+        unsafe { CStr::from_ptr(pg_tap_ifname(self.brick)).to_string_lossy().into_owned() }
+    }
+
+    pub fn mac(&self) -> [u8; 6] {
+        let mut addr: ether_addr = unsafe { mem::zeroed() };
+        unsafe {
+            pg_tap_get_mac(self.brick, &mut addr);
+        }
+        addr.addr_bytes
+    }
+
+    // Host side configuration of the tap interface, done through rtnetlink
+    // so the caller needs CAP_NET_ADMIN.
+    pub fn set_up(&mut self) -> Result<(), Error> {
+        let ifname = self.ifname();
+        netlink_result(netlink::set_link_up(&ifname, true), "set up", &ifname)
+    }
+
+    pub fn set_down(&mut self) -> Result<(), Error> {
+        let ifname = self.ifname();
+        netlink_result(netlink::set_link_up(&ifname, false), "set down", &ifname)
+    }
+
+    pub fn add_address(&mut self, addr: IpAddr, prefix: u8) -> Result<(), Error> {
+        let ifname = self.ifname();
+        netlink_result(netlink::add_address(&ifname, addr, prefix),
+                       &format!("add address {}/{} on", addr, prefix),
+                       &ifname)
+    }
+
+    pub fn set_mtu(&mut self, mtu: u32) -> Result<(), Error> {
+        let ifname = self.ifname();
+        netlink_result(netlink::set_mtu(&ifname, mtu), "set mtu of", &ifname)
+    }
+
+    pub fn set_mac(&mut self, mac: [u8; 6]) -> Result<(), Error> {
+        let ifname = self.ifname();
+        netlink_result(netlink::set_mac(&ifname, mac), "set mac of", &ifname)
+    }
+}
+
+fn netlink_result(r: io::Result<()>, action: &str, ifname: &str) -> Result<(), Error> {
+    match r {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            let mut error = Error::new();
+            error.set(format!("cannot {} {}: {}", action, ifname, e));
+            Err(error)
+        }
+    }
 }
 
 impl Drop for Tap {
@@ -50,3 +127,50 @@ impl Drop for Tap {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::init;
+    use libc;
+    use std::io;
+    use std::thread;
+
+    #[test]
+    fn ifname_mac() {
+        init();
+        let tap = Tap::with_ifname("tap", "pgtest0").unwrap();
+        assert_eq!(tap.ifname(), "pgtest0");
+        assert!(tap.mac() != [0; 6]);
+        let tap = Tap::new("tap");
+        assert!(!tap.ifname().is_empty());
+    }
+
+    #[test]
+    fn host_config() {
+        init();
+        // Work in a private network namespace so the host is left untouched.
+        // unshare() only moves the calling thread, which exits afterwards.
+        // Entering a user namespace is not allowed from the multithreaded
+        // test harness, so skip when we are not privileged enough.
+        thread::spawn(|| {
+                if unsafe { libc::unshare(libc::CLONE_NEWNET) } != 0 {
+                    let err = io::Error::last_os_error();
+                    assert_eq!(err.raw_os_error(), Some(libc::EPERM));
+                    println!("skipping host_config: cannot create network namespace");
+                    return;
+                }
+                let mut tap = Tap::with_ifname("tap", "pgtest1").unwrap();
+                tap.set_mtu(1400).unwrap();
+                tap.set_mac([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]).unwrap();
+                tap.set_up().unwrap();
+                tap.add_address("10.0.0.1".parse().unwrap(), 24).unwrap();
+                tap.add_address("fd00::1".parse().unwrap(), 64).unwrap();
+                assert!(tap.add_address("10.0.0.1".parse().unwrap(), 24).is_err());
+                assert!(tap.add_address("10.0.0.2".parse().unwrap(), 33).is_err());
+                tap.set_down().unwrap();
+            })
+            .join()
+            .unwrap();
+    }
+}
@@ -21,50 +21,213 @@ use packetgraph_sys::{pg_brick, pg_brick_destroy, pg_vhost_new, pg_vhost_start,
                       pg_vhost_socket_path, PG_VHOST_USER_CLIENT, PG_VHOST_USER_NO_RECONNECT,
                       PG_VHOST_USER_DEQUEUE_ZERO_COPY};
 
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::Duration;
 use std::ffi::CStr;
+use std::fs;
+use std::io::prelude::*;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
 
 lazy_static! {
-    static ref VHOST_INITIALIZED: Mutex<bool> = Mutex::new(false);
+    // Socket directory passed to pg_vhost_start, if started
+    static ref VHOST_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+const VHOST_DEFAULT_DIR: &str = "/tmp";
+const VHOST_WATCH_PERIOD_MS: u64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VhostEvent {
+    Connected,
+    Disconnected,
+}
+
+bitflags! {
+    pub struct VhostFlags: u64 {
+        // packetgraph connects to the socket (QEMU is the server)
+        const CLIENT = PG_VHOST_USER_CLIENT as u64;
+        // Don't try to reconnect when QEMU goes away, client mode only
+        const NO_RECONNECT = PG_VHOST_USER_NO_RECONNECT as u64;
+        const DEQUEUE_ZERO_COPY = PG_VHOST_USER_DEQUEUE_ZERO_COPY as u64;
+    }
 }
 
 pub const VHOST_USER_CLIENT: u64 = PG_VHOST_USER_CLIENT as u64;
 pub const VHOST_USER_NO_RECONNECT: u64 = PG_VHOST_USER_NO_RECONNECT as u64;
 pub const VHOST_USER_DEQUEUE_ZERO_COPY: u64 = PG_VHOST_USER_DEQUEUE_ZERO_COPY as u64;
 
+impl From<VhostFlags> for u64 {
+    fn from(flags: VhostFlags) -> u64 {
+        flags.bits()
+    }
+}
+
+// Raw VHOST_USER_* flags, unknown bits are an error
+fn check_flags(flags: u64) -> Result<VhostFlags, Error> {
+    match VhostFlags::from_bits(flags) {
+        Some(f) => Ok(f),
+        None => {
+            let mut error = Error::new();
+            error.set(format!("unknown vhost flags {:#x}", flags & !VhostFlags::all().bits()));
+            Err(error)
+        }
+    }
+}
+
+// Virtio options of generated QEMU arguments, see Vhost::qemu_args_with()
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QemuOptions {
+    // Let the guest offload checksum computing
+    pub csum: bool,
+    // Let the guest send large segments (needs csum)
+    pub gso: bool,
+}
+
+pub struct VhostConfig {
+    name: String,
+    flags: VhostFlags,
+    socket: Option<PathBuf>,
+}
+
+impl VhostConfig {
+    pub fn new<S: Into<String>>(name: S) -> VhostConfig {
+        VhostConfig {
+            name: name.into(),
+            flags: VhostFlags::empty(),
+            socket: None,
+        }
+    }
+
+    pub fn client(mut self, client: bool) -> VhostConfig {
+        self.flags.set(VhostFlags::CLIENT, client);
+        self
+    }
+
+    pub fn reconnect(mut self, reconnect: bool) -> VhostConfig {
+        self.flags.set(VhostFlags::NO_RECONNECT, !reconnect);
+        self
+    }
+
+    pub fn dequeue_zero_copy(mut self, zero_copy: bool) -> VhostConfig {
+        self.flags.set(VhostFlags::DEQUEUE_ZERO_COPY, zero_copy);
+        self
+    }
+
+    pub fn socket_path<P: Into<PathBuf>>(mut self, socket: P) -> VhostConfig {
+        self.socket = Some(socket.into());
+        self
+    }
+
+    pub fn build(self) -> Result<Vhost, Error> {
+        match self.socket {
+            Some(socket) => Vhost::with_socket_path(self.name, socket, self.flags),
+            None => Vhost::new(self.name, self.flags),
+        }
+    }
+}
+
+// Start vhost-user support, sockets will be created inside `dir`.
+// Calling it again with the same directory does nothing, another directory is
+// an error. If never called, the first Vhost uses /tmp.
+pub fn start<P: AsRef<Path>>(dir: P) -> Result<(), Error> {
+    let mut vhost_dir = VHOST_DIR.lock().unwrap();
+    start_locked(&mut vhost_dir, dir.as_ref())
+}
+
+fn start_locked(vhost_dir: &mut Option<PathBuf>, dir: &Path) -> Result<(), Error> {
+    let mut error = Error::new();
+    if let Some(ref d) = *vhost_dir {
+        if d.as_path() == dir {
+            return Ok(());
+        }
+        error.set(format!("vhost already started in {}, cannot use {}",
+                          d.display(),
+                          dir.display()));
+        return Err(error);
+    }
+    let cdir = match dir.to_str() {
+        Some(d) => CString::new(d).unwrap(),
+        None => {
+            error.set(format!("invalid vhost directory {}", dir.display()));
+            return Err(error);
+        }
+    };
+    if unsafe { pg_vhost_start(cdir.as_ptr(), &mut error.ptr) } < 0 {
+        assert!(error.is_set());
+        return Err(error);
+    }
+    *vhost_dir = Some(dir.to_path_buf());
+    Ok(())
+}
+
+// Start vhost-user in the default directory if not started yet, returns the
+// socket directory
+fn default_start() -> Result<PathBuf, Error> {
+    let mut vhost_dir = VHOST_DIR.lock().unwrap();
+    if vhost_dir.is_none() {
+        start_locked(&mut vhost_dir, Path::new(VHOST_DEFAULT_DIR))?;
+    }
+    Ok(vhost_dir.clone().unwrap())
+}
+
+// QEMU arguments giving the VM mem_size MB of memory shared through
+// hugepages, as vhost-user needs. Give them once per VM, whatever the number
+// of vhost NICs.
+pub fn qemu_memory_args(mem_size: u64, hugepage_path: &Path) -> Result<Vec<String>, Error> {
+    let hugepage_path = match hugepage_path.to_str() {
+        Some(p) => p,
+        None => {
+            let mut error = Error::new();
+            error.set(format!("invalid hugepage path {}", hugepage_path.display()));
+            return Err(error);
+        }
+    };
+    Ok(vec![String::from("-m"),
+            format!("{}M", mem_size),
+            String::from("-object"),
+            format!("memory-backend-file,id=mem,size={}M,mem-path={},share=on",
+                    mem_size,
+                    qemu_escape(hugepage_path)),
+            String::from("-numa"),
+            String::from("node,memdev=mem")])
+}
+
+// QEMU option values escape commas by doubling them
+fn qemu_escape(s: &str) -> String {
+    s.replace(",", ",,")
+}
+
 pub struct Vhost {
     pub brick: *mut pg_brick,
     pub name: String,
+    flags: VhostFlags,
+    // Set to false on drop to stop subscribe() watchers
+    alive: Arc<AtomicBool>,
+    // User chosen socket path and the symlink made with the packetgraph one
+    socket_path: Option<PathBuf>,
+    socket_link: Option<PathBuf>,
 }
 
 impl Vhost {
-    // flags:
-    // - VHOST_USER_CLIENT
-    // - VHOST_USER_NO_RECONNECT
-    // - VHOST_USER_DEQUEUE_ZERO_COPY
-    pub fn new<S: Into<String>>(name: S, flags: u64) -> Result<Vhost, Error> {
-        {
-            let mut vhi = VHOST_INITIALIZED.lock().unwrap();
-            let mut error = Error::new();
-            if !*vhi {
-                let ret = unsafe {
-                    pg_vhost_start(CString::new("/tmp").unwrap().as_ptr(), &mut error.ptr)
-                };
-                if ret < 0 {
-                    assert!(error.is_set());
-                    return Err(error);
-                } else {
-                    *vhi = true;
-                }
-            }
-        }
+    // flags: VhostFlags or raw VHOST_USER_* values
+    pub fn new<S: Into<String>, F: Into<u64>>(name: S, flags: F) -> Result<Vhost, Error> {
+        let flags = check_flags(flags.into())?;
+        default_start()?;
         let mut error = Error::new();
         let name = name.into();
         let cname = CString::new(name.as_str()).unwrap();
         let b = unsafe {
             Vhost {
-                brick: pg_vhost_new(cname.as_ptr(), flags as u64, &mut error.ptr),
+                brick: pg_vhost_new(cname.as_ptr(), flags.bits(), &mut error.ptr),
                 name: name,
+                flags: flags,
+                alive: Arc::new(AtomicBool::new(true)),
+                socket_path: None,
+                socket_link: None,
             }
         };
         match error.is_set() {
@@ -73,10 +236,71 @@ impl Vhost {
         }
     }
 
+    // Same as new() but make the vhost-user socket reachable at `socket`.
+    // packetgraph always names its socket after the brick in the vhost
+    // directory, so a symlink is made between both paths: `socket` points to
+    // the packetgraph socket in server mode, and the packetgraph socket
+    // points to `socket` (created by QEMU) in client mode. The link is made
+    // before the brick in client mode as packetgraph connects right away.
+    // Nothing is removed: it is an error if the link path already exists.
+    pub fn with_socket_path<S: Into<String>, P: AsRef<Path>, F: Into<u64>>(name: S,
+                                                                           socket: P,
+                                                                           flags: F)
+                                                                           -> Result<Vhost, Error> {
+        let flags = check_flags(flags.into())?;
+        let name = name.into();
+        let socket = socket.as_ref().to_path_buf();
+        let path = default_start()?.join(format!("qemu-{}", name));
+        let client = flags.contains(VhostFlags::CLIENT);
+        let (src, dst) = match client {
+            false => (path, socket.clone()),
+            true => (socket.clone(), path),
+        };
+        let mut error = Error::new();
+        if fs::symlink_metadata(&dst).is_ok() {
+            error.set(format!("cannot link {}: path already exists", dst.display()));
+            return Err(error);
+        }
+        let link = || {
+            symlink(&src, &dst).map_err(|e| {
+                let mut error = Error::new();
+                error.set(format!("cannot link {} to {}: {}", dst.display(), src.display(), e));
+                error
+            })
+        };
+        if client {
+            link()?;
+        }
+        let mut b = match Vhost::new(name, flags) {
+            Ok(b) => b,
+            Err(e) => {
+                if client {
+                    let _ = fs::remove_file(&dst);
+                }
+                return Err(e);
+            }
+        };
+        if !client {
+            link()?;
+        }
+        b.socket_path = Some(socket);
+        b.socket_link = Some(dst);
+        Ok(b)
+    }
+
     pub fn pollable(&self) -> bool {
         true
     }
 
+    pub fn flags(&self) -> VhostFlags {
+        self.flags
+    }
+
+    // Socket path given to with_socket_path()
+    pub fn socket_path(&self) -> Option<&Path> {
+        self.socket_path.as_deref()
+    }
+
     pub fn path(&self) -> Result<String, Error> {
         let mut error = Error::new();
         unsafe {
@@ -89,10 +313,133 @@ impl Vhost {
             return Ok(path);
         }
     }
+
+    // QEMU arguments to plug a virtio-net device on this vhost, one set per
+    // NIC: VM memory is set once with qemu_memory_args(). QEMU is started as
+    // socket server when the vhost is in client mode.
+    pub fn qemu_args(&self, mac: [u8; 6]) -> Result<Vec<String>, Error> {
+        self.qemu_args_with(mac, QemuOptions::default())
+    }
+
+    pub fn qemu_args_with(&self, mac: [u8; 6], options: QemuOptions) -> Result<Vec<String>, Error> {
+        if options.gso && !options.csum {
+            let mut error = Error::new();
+            error.set("gso needs csum offload");
+            return Err(error);
+        }
+        let socket = match self.socket_path {
+            Some(ref p) => p.to_string_lossy().into_owned(),
+            None => self.path()?,
+        };
+        let server = match self.flags.contains(VhostFlags::CLIENT) {
+            true => ",server,nowait",
+            false => "",
+        };
+        let on_off = |b: bool| if b { "on" } else { "off" };
+        let mac = mac.iter().map(|b| format!("{:02x}", b)).collect::<Vec<String>>().join(":");
+        let name = qemu_escape(&self.name);
+        Ok(vec![String::from("-chardev"),
+                format!("socket,id=char-{},path={}{}", name, qemu_escape(&socket), server),
+                String::from("-netdev"),
+                format!("type=vhost-user,id=net-{},chardev=char-{},vhostforce", name, name),
+                String::from("-device"),
+                format!("virtio-net-pci,csum={},gso={},mac={},netdev=net-{}",
+                        on_off(options.csum),
+                        on_off(options.gso),
+                        mac,
+                        name)])
+    }
+
+    // Whether a unix socket bound to the vhost-user socket path is connected
+    // (state 03 in /proc/net/unix). This is a raw socket check: anything
+    // connecting to the socket counts, whether the vhost-user handshake went
+    // through or not, and only sockets of the caller's network namespace are
+    // seen. packetgraph handles the vhost device callbacks internally and
+    // exports no hook for them, so device readiness cannot be reported.
+    pub fn is_connected(&self) -> bool {
+        match self.path() {
+            Ok(p) => socket_connected(&socket_target(&p)),
+            Err(_) => false,
+        }
+    }
+
+    // Get Connected/Disconnected events, as is_connected() checked every 100ms by a watcher
+    // thread. The thread ends with the Vhost, a dropped Receiver is only noticed on the
+    // next event.
+    pub fn subscribe(&mut self) -> Result<Receiver<VhostEvent>, Error> {
+        let path = socket_target(&self.path()?);
+        let alive = self.alive.clone();
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            let mut connected = false;
+            while alive.load(Ordering::Relaxed) {
+                let now = socket_connected(&path);
+                if now != connected {
+                    connected = now;
+                    let event = match now {
+                        true => VhostEvent::Connected,
+                        false => VhostEvent::Disconnected,
+                    };
+                    if tx.send(event).is_err() {
+                        return;
+                    }
+                }
+                thread::sleep(Duration::from_millis(VHOST_WATCH_PERIOD_MS));
+            }
+        });
+        Ok(rx)
+    }
+}
+
+// Resolve links made by with_socket_path()
+fn socket_target(path: &str) -> String {
+    match fs::canonicalize(path) {
+        Ok(p) => p.to_string_lossy().into_owned(),
+        Err(_) => String::from(path),
+    }
+}
+
+// Look in /proc/net/unix for a socket bound to `path` in connected state
+fn socket_connected(path: &str) -> bool {
+    let mut table = String::new();
+    match fs::File::open("/proc/net/unix") {
+        Ok(mut f) => {
+            if f.read_to_string(&mut table).is_err() {
+                return false;
+            }
+        }
+        Err(_) => return false,
+    }
+    unix_table_connected(&table, path)
+}
+
+// Columns are Num RefCount Protocol Flags Type St Inode Path. The path is
+// printed as is and may hold spaces, so it is the whole end of the line.
+fn unix_table_connected(table: &str, path: &str) -> bool {
+    table.lines().skip(1).any(|line| {
+        let mut rest = line.trim_start();
+        let mut state = "";
+        for i in 0..7 {
+            let end = match rest.find(char::is_whitespace) {
+                Some(end) => end,
+                // No path
+                None => return false,
+            };
+            if i == 5 {
+                state = &rest[..end];
+            }
+            rest = rest[end..].trim_start();
+        }
+        state == "03" && rest == path
+    })
 }
 
 impl Drop for Vhost {
     fn drop(&mut self) {
+        self.alive.store(false, Ordering::Relaxed);
+        if let Some(ref link) = self.socket_link {
+            let _ = fs::remove_file(link);
+        }
         unsafe {
             pg_brick_destroy(self.brick);
         }
@@ -104,6 +451,7 @@ mod tests {
     use super::*;
     use super::super::init;
     use super::super::brick::Brick;
+    use std::os::unix::net::UnixStream;
 
     #[test]
     fn rings() {
@@ -118,4 +466,82 @@ mod tests {
         b1.poll().unwrap();
         b2.poll().unwrap();
     }
+
+    #[test]
+    fn socket_dir() {
+        init();
+        start(VHOST_DEFAULT_DIR).unwrap();
+        start(VHOST_DEFAULT_DIR).unwrap();
+        assert!(start("/var/tmp").is_err());
+        let socket = Path::new("/tmp/pg-vhost-custom-socket");
+        let vhost = Vhost::with_socket_path("vhost3", socket, VhostFlags::empty()).unwrap();
+        assert_eq!(fs::read_link(socket).unwrap(), PathBuf::from(vhost.path().unwrap()));
+        assert!(Vhost::with_socket_path("vhost3b", socket, VhostFlags::empty()).is_err());
+        drop(vhost);
+        assert!(fs::symlink_metadata(socket).is_err());
+    }
+
+    #[test]
+    fn flags() {
+        init();
+        let vhost = VhostConfig::new("vhost4")
+            .client(true)
+            .reconnect(false)
+            .build()
+            .unwrap();
+        assert_eq!(vhost.flags(), VhostFlags::CLIENT | VhostFlags::NO_RECONNECT);
+        assert!(VhostFlags::from_bits(1 << 63).is_none());
+        assert!(Vhost::new("vhost4b", VHOST_USER_CLIENT | 1 << 63).is_err());
+        let socket = Path::new("/tmp/pg-vhost-garbage-flags");
+        assert!(Vhost::with_socket_path("vhost4c", socket, 1u64 << 63).is_err());
+        assert!(fs::symlink_metadata(socket).is_err());
+        assert_eq!(u64::from(VhostFlags::CLIENT), VHOST_USER_CLIENT);
+    }
+
+    #[test]
+    fn connection_events() {
+        init();
+        let mut vhost = Vhost::new("vhost5", VhostFlags::empty()).unwrap();
+        let events = vhost.subscribe().unwrap();
+        assert!(!vhost.is_connected());
+        // Fake vhost-user client
+        let client = UnixStream::connect(vhost.path().unwrap()).unwrap();
+        assert_eq!(events.recv_timeout(Duration::from_secs(5)), Ok(VhostEvent::Connected));
+        assert!(vhost.is_connected());
+        drop(client);
+        assert_eq!(events.recv_timeout(Duration::from_secs(5)), Ok(VhostEvent::Disconnected));
+        assert!(!vhost.is_connected());
+    }
+
+    #[test]
+    fn unix_table() {
+        let table = "Num       RefCount Protocol Flags    Type St Inode Path\n\
+                     0000000000000000: 00000002 00000000 00010000 0001 01 1234 /tmp/a b\n\
+                     0000000000000000: 00000003 00000000 00000000 0001 03 1235 /tmp/a b\n\
+                     0000000000000000: 00000003 00000000 00000000 0001 03 1236\n";
+        assert!(unix_table_connected(table, "/tmp/a b"));
+        assert!(!unix_table_connected(table, "/tmp/a"));
+        assert!(!unix_table_connected(table, ""));
+    }
+
+    #[test]
+    fn qemu() {
+        init();
+        let vhost = Vhost::new("vhost6", VhostFlags::empty()).unwrap();
+        let mac = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+        let args = vhost.qemu_args(mac).unwrap();
+        let path = vhost.path().unwrap();
+        assert!(args.contains(&format!("socket,id=char-vhost6,path={}", path)));
+        assert!(args.contains(&String::from("virtio-net-pci,csum=off,gso=off,\
+                                             mac=52:54:00:12:34:56,netdev=net-vhost6")));
+        assert!(!args.contains(&String::from("-m")));
+        let mem = qemu_memory_args(124, Path::new("/mnt/huge")).unwrap();
+        assert!(mem.contains(&String::from("memory-backend-file,id=mem,size=124M,\
+                                            mem-path=/mnt/huge,share=on")));
+        let options = QemuOptions {
+            csum: false,
+            gso: true,
+        };
+        assert!(vhost.qemu_args_with(mac, options).is_err());
+    }
 }